@@ -1,13 +1,28 @@
-use rust_indexed::ranking::{Ranking, SearchFlags};
+use std::path::Path;
 
-fn main() -> tantivy::Result<()> {
+use rust_indexed::error::Error;
+use rust_indexed::query::BookFilter;
+use rust_indexed::ranking::{Ranking, SearchFlags, DEFAULT_LIMIT};
+use rust_indexed::INDEX_DIR;
+
+fn main() -> Result<(), Error> {
     let needle = std::env::args().nth(1).expect("usage: $1 phrase");
 
-    let ranking = Ranking::new();
+    let ranking = Ranking::open(Path::new(INDEX_DIR))?;
+
+    let page = ranking.search(
+        &needle,
+        &BookFilter::default(),
+        SearchFlags::DEFAULT,
+        0,
+        DEFAULT_LIMIT,
+    )?;
 
-    for r in ranking.search(&needle, SearchFlags::DEFAULT) {
+    for r in page.results {
         dbg!(r);
     }
 
+    println!("{} results", page.total);
+
     Ok(())
 }
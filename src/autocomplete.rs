@@ -0,0 +1,370 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+
+use crate::error::Error;
+use crate::index::SearchIndex;
+
+/// Default number of completions
+pub const DEFAULT_COMPLETIONS: usize = 8;
+
+/// Maximal number of completions
+pub const MAX_COMPLETIONS: usize = 20;
+
+/// Terms in fewer documents aren't completed to
+const MIN_TERM_DOC_FREQ: u64 = 2;
+
+/// Terms shorter than this aren't completed to
+const MIN_TERM_LEN: usize = 3;
+
+/// Weight of a chapter with a title or heading against a document with a term, titles
+/// are what people look for
+const TITLE_WEIGHT: u64 = 10;
+
+/// Weight of a search of a completion, like a chapter with it as title
+const SEARCH_WEIGHT: u64 = 10;
+
+/// Number of queries whose searches are counted, the ones searched once are forgotten
+/// when there are more
+const MAX_COUNTED_QUERIES: usize = 10_000;
+
+/// Completions of partially typed queries, ie. `life` to `lifetimes` and `Arc<Mu` to
+/// `Arc<Mutex<T>>`.
+///
+/// Completions are chapter titles, section headings and their code-like words, and the
+/// frequent terms of the indexes. They are sorted by their lowercase forms, so the ones
+/// starting with a prefix are found by a binary search. The query is completed as a
+/// whole and by its last word, the completions in the most documents and searched the
+/// most first.
+#[derive(Debug, Default, Clone)]
+pub struct Autocomplete {
+    /// Sorted by the key
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    /// Lowercase text
+    key: String,
+    text: String,
+    weight: u64,
+}
+
+/// Collects the completions before they are sorted.
+#[derive(Debug, Default)]
+pub struct AutocompleteBuilder {
+    entries: HashMap<String, Entry>,
+    /// Urls of the chapters with the lowercase titles added for them
+    chapter_titles: HashSet<(String, String)>,
+}
+
+impl AutocompleteBuilder {
+    /// Adds the text with the weight, the weights of texts differing in case add up and
+    /// the first form is kept.
+    pub fn add(&mut self, text: &str, weight: u64) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        let key = text.to_lowercase();
+        self.entries
+            .entry(key.clone())
+            .or_insert_with(|| Entry {
+                key,
+                text: text.to_string(),
+                weight: 0,
+            })
+            .weight += weight;
+    }
+
+    /// Adds the titles and headings of the chapters of the index, and their words
+    /// which look like code, ie. `Arc<Mutex<T>>` of "Sharing Arc<Mutex<T>> Between
+    /// Threads". Each one counts once per chapter, whatever the number of its sections
+    /// and code blocks, in any of the indexes.
+    pub fn add_titles(&mut self, index: &SearchIndex) -> Result<(), Error> {
+        for (chapter_url, title) in index.chapter_titles()? {
+            let words = title
+                .split_whitespace()
+                .filter(|word| is_code(word))
+                .map(|word| word.trim_end_matches([',', '.', ':', ';']));
+
+            for text in std::iter::once(title.as_str()).chain(words) {
+                if self
+                    .chapter_titles
+                    .insert((chapter_url.clone(), text.trim().to_lowercase()))
+                {
+                    self.add(text, TITLE_WEIGHT);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the terms of the index in enough documents.
+    pub fn add_terms(&mut self, index: &SearchIndex) -> Result<(), Error> {
+        for (term, doc_freq) in index.word_frequencies()? {
+            if doc_freq >= MIN_TERM_DOC_FREQ && term.chars().count() >= MIN_TERM_LEN {
+                self.add(&term, doc_freq);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn build(self) -> Autocomplete {
+        let mut entries: Vec<Entry> = self.entries.into_values().collect();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Autocomplete { entries }
+    }
+}
+
+impl Autocomplete {
+    /// Completions of the titles, headings and terms of the indexes.
+    pub fn from_indexes(indexes: &[&SearchIndex]) -> Result<Self, Error> {
+        let mut builder = AutocompleteBuilder::default();
+
+        for index in indexes {
+            builder.add_titles(index)?;
+            builder.add_terms(index)?;
+        }
+
+        Ok(builder.build())
+    }
+
+    /// At most `limit` completions of the query, of the whole query and of its last
+    /// word, the heaviest first. Completions which were searched weigh more.
+    pub fn complete(&self, q: &str, limit: usize, searches: &QueryCounts) -> Vec<String> {
+        let q = q.trim_start();
+        if q.trim().is_empty() {
+            return vec![];
+        }
+
+        let mut completions: Vec<(u64, String)> = self
+            .starting_with(q)
+            .map(|entry| (entry.weight, entry.text.clone()))
+            .collect();
+
+        // The last word is completed when the query doesn't end with a whitespace
+        if let Some((head, last)) = q.rsplit_once(char::is_whitespace) {
+            if !last.is_empty() {
+                completions
+                    .extend(self.starting_with(last).map(|entry| {
+                        (entry.weight, format!("{} {}", head.trim_end(), entry.text))
+                    }));
+            }
+        }
+
+        // Completions differing in case add up, the first form is kept
+        let mut weights = HashMap::<String, (u64, String)>::new();
+        for (weight, text) in completions {
+            weights
+                .entry(text.to_lowercase())
+                .or_insert_with(|| (0, text))
+                .0 += weight;
+        }
+
+        let mut completions: Vec<(u64, String)> = weights
+            .into_iter()
+            .map(|(_, (weight, text))| (weight + searches.count(&text) * SEARCH_WEIGHT, text))
+            .collect();
+        completions.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        completions
+            .into_iter()
+            .take(limit)
+            .map(|(_, text)| text)
+            .collect()
+    }
+
+    /// Entries with keys starting with the lowercase prefix.
+    fn starting_with(&self, prefix: &str) -> impl Iterator<Item = &Entry> {
+        let prefix = prefix.to_lowercase();
+        let start = self.entries.partition_point(|entry| entry.key < prefix);
+
+        self.entries[start..]
+            .iter()
+            .take_while(move |entry| entry.key.starts_with(&prefix))
+    }
+}
+
+/// Numbers of searches of the queries with results, by their lowercase forms, to
+/// complete the popular ones first. Shared by the rankings, so they are kept when the
+/// indexes are reloaded.
+#[derive(Debug, Default)]
+pub struct QueryCounts {
+    counts: RwLock<HashMap<String, u64>>,
+}
+
+impl QueryCounts {
+    /// Counts a search of the query. When too many queries are counted, the counts
+    /// are halved and the ones searched once are forgotten.
+    pub fn record(&self, q: &str) {
+        let key = query_key(q);
+        if key.is_empty() {
+            return;
+        }
+
+        let mut counts = self.counts.write().unwrap();
+        if counts.len() >= MAX_COUNTED_QUERIES && !counts.contains_key(&key) {
+            counts.retain(|_, count| {
+                *count /= 2;
+                *count > 0
+            });
+        }
+        *counts.entry(key).or_default() += 1;
+    }
+
+    /// Number of searches of the query, whatever its case and whitespace.
+    pub fn count(&self, q: &str) -> u64 {
+        let counts = self.counts.read().unwrap();
+        counts.get(&query_key(q)).copied().unwrap_or(0)
+    }
+}
+
+/// Lowercase query with its words separated by single spaces.
+fn query_key(q: &str) -> String {
+    q.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Whether the word has characters of code, like generics or paths.
+fn is_code(word: &str) -> bool {
+    word.contains(['<', ':', '!', '&', '(', '_']) && word.chars().any(char::is_alphabetic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{document, index_sources, rust, TestSource};
+    use crate::{CODE_INDEX, PAGE_INDEX};
+
+    fn autocomplete() -> Autocomplete {
+        let mut builder = AutocompleteBuilder::default();
+        for (text, weight) in [
+            ("Lifetimes", 10),
+            ("lifetimes", 30),
+            ("lifetime", 12),
+            ("life", 2),
+            ("Validating References with Lifetimes", 10),
+            ("Arc<Mutex<T>>", 10),
+            ("Arc<T>", 20),
+            ("mutex", 15),
+        ] {
+            builder.add(text, weight);
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn test_complete() {
+        let autocomplete = autocomplete();
+        let searches = QueryCounts::default();
+        let complete = |q, limit| autocomplete.complete(q, limit, &searches);
+
+        assert_eq!(
+            complete("life", DEFAULT_COMPLETIONS),
+            ["Lifetimes", "lifetime", "life"]
+        );
+        assert_eq!(complete("Arc<Mu", DEFAULT_COMPLETIONS), ["Arc<Mutex<T>>"]);
+        assert_eq!(
+            complete("sharing arc<", DEFAULT_COMPLETIONS),
+            ["sharing Arc<T>", "sharing Arc<Mutex<T>>"]
+        );
+        assert_eq!(
+            complete("validating ref", 1),
+            ["Validating References with Lifetimes"]
+        );
+        assert!(complete("life ", DEFAULT_COMPLETIONS).is_empty());
+        assert!(complete(" ", DEFAULT_COMPLETIONS).is_empty());
+    }
+
+    #[test]
+    fn test_popular_completions() {
+        let mut builder = AutocompleteBuilder::default();
+        for (text, weight) in [
+            ("Sharing Arc<Mutex<T>>", 15),
+            ("Arc<Mutex<T>>", 10),
+            ("Arc<T>", 20),
+            ("lifetimes", 40),
+            ("lifetime", 12),
+        ] {
+            builder.add(text, weight);
+        }
+        let autocomplete = builder.build();
+        let searches = QueryCounts::default();
+
+        // Of the whole query and of the last word, the weights add up
+        assert_eq!(
+            autocomplete.complete("sharing arc<", DEFAULT_COMPLETIONS, &searches),
+            ["Sharing Arc<Mutex<T>>", "sharing Arc<T>"]
+        );
+
+        for _ in 0..3 {
+            searches.record(" Lifetime");
+        }
+        assert_eq!(searches.count("lifetime"), 3);
+        assert_eq!(
+            autocomplete.complete("life", DEFAULT_COMPLETIONS, &searches),
+            ["lifetime", "lifetimes"]
+        );
+    }
+
+    #[test]
+    fn test_titles_once_per_chapter() {
+        let section = |url, title, heading: &str, code: &[&str]| {
+            let mut document = document(url, title, "threads share state", rust(code));
+            document.metadata.heading = heading.to_string();
+            document
+        };
+        let source = TestSource {
+            title: "Guide",
+            documents: vec![
+                section(
+                    "guide/state#arc",
+                    "Shared State",
+                    "Sharing Arc<Mutex<T>>",
+                    &["let a = 1;", "let b = 2;", "let c = 3;"],
+                ),
+                section("guide/state#mutex", "Shared State", "Using Mutex", &[]),
+                section(
+                    "guide/threads",
+                    "Threads",
+                    "Sharing Arc<Mutex<T>>",
+                    &["let d = 4;"],
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        index_sources(dir.path(), &[("guide", &source)]);
+        let open = |name| SearchIndex::open(&dir.path().join(name).to_string_lossy()).unwrap();
+        let autocomplete =
+            Autocomplete::from_indexes(&[&open(PAGE_INDEX), &open(CODE_INDEX)]).unwrap();
+
+        let weight = |key: &str| {
+            autocomplete
+                .entries
+                .iter()
+                .find(|entry| entry.key == key)
+                .map(|entry| entry.weight)
+        };
+        // Once for the sections and code blocks of the chapter
+        assert_eq!(weight("shared state"), Some(TITLE_WEIGHT));
+        assert_eq!(weight("using mutex"), Some(TITLE_WEIGHT));
+        // Once in each chapter
+        assert_eq!(weight("sharing arc<mutex<t>>"), Some(2 * TITLE_WEIGHT));
+    }
+
+    #[test]
+    fn test_code_words() {
+        assert!(is_code("Arc<Mutex<T>>"));
+        assert!(is_code("std::sync"));
+        assert!(!is_code("Threads"));
+        assert!(!is_code("::"));
+    }
+}
@@ -0,0 +1,49 @@
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::sources::SourceConfig;
+use crate::tokenizer::AnalyzerConfig;
+
+/// Contents of `config.toml`.
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// Analysis of the page prose, the defaults are used without `[analyzer]`
+    #[serde(default)]
+    pub analyzer: AnalyzerConfig,
+    pub sources: Vec<SourceConfig>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let buf = std::fs::read_to_string(path)?;
+        toml::from_str(&buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::SourceOptions;
+
+    #[test]
+    fn test_load_config() {
+        let config = Config::load("config.toml").unwrap();
+
+        assert_eq!(config.analyzer, AnalyzerConfig::default());
+
+        assert!(matches!(
+            &config.sources[0].options,
+            SourceOptions::Mdbook { summary } if summary == "SUMMARY.md"
+        ));
+        assert!(config
+            .sources
+            .iter()
+            .any(|source| matches!(source.options, SourceOptions::HtmlDir { .. })));
+        assert!(config
+            .sources
+            .iter()
+            .any(|source| matches!(source.options, SourceOptions::MarkdownDir { .. })));
+    }
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::io;
+
+use tantivy::TantivyError;
+
+/// Errors of opening, building and searching the indexes.
+#[derive(Debug)]
+pub enum Error {
+    /// The query can't be parsed, `position` is the byte offset of the error in the
+    /// query when it's known
+    QuerySyntax {
+        message: String,
+        position: Option<usize>,
+    },
+    /// There is no index in the directory
+    MissingIndex(String),
+    /// The files of the index can't be read
+    CorruptIndex {
+        dir: String,
+        message: String,
+    },
+    /// The index was built with another version of the schema, `None` for the indexes
+    /// built before the version was stored with them
+    SchemaVersion {
+        dir: String,
+        found: Option<u32>,
+        expected: u32,
+    },
+    /// The fields of the index aren't the ones of its schema version
+    SchemaMismatch(String),
+    /// A document has no stored value of the field
+    MissingStoredField {
+        dir: String,
+        field: String,
+    },
+    /// The index was opened for searching, documents can't be added or deleted
+    ReadOnly(String),
+    Index(TantivyError),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::QuerySyntax {
+                message,
+                position: Some(position),
+            } => write!(f, "Invalid query at {}: {}", position, message),
+            Error::QuerySyntax {
+                message,
+                position: None,
+            } => write!(f, "Invalid query: {}", message),
+            Error::MissingIndex(dir) => write!(f, "No index in `{}`", dir),
+            Error::CorruptIndex { dir, message } => {
+                write!(f, "`{}` index can't be read: {}", dir, message)
+            }
+            Error::SchemaVersion {
+                dir,
+                found: Some(found),
+                expected,
+            } => write!(
+                f,
+                "`{}` index has schema version {}, expected {}, run `migrate` to upgrade it",
+                dir, found, expected
+            ),
+            Error::SchemaVersion {
+                dir,
+                found: None,
+                expected,
+            } => write!(
+                f,
+                "`{}` index has no schema version, expected {}, run `migrate` to upgrade it",
+                dir, expected
+            ),
+            Error::SchemaMismatch(dir) => write!(
+                f,
+                "Fields of `{}` index don't match its schema version, rebuild it with `index --full`",
+                dir
+            ),
+            Error::MissingStoredField { dir, field } => write!(
+                f,
+                "A document in `{}` index has no stored `{}` field",
+                dir, field
+            ),
+            Error::ReadOnly(dir) => write!(f, "`{}` index was opened read-only", dir),
+            Error::Index(err) => write!(f, "{}", err),
+            Error::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Index(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<TantivyError> for Error {
+    fn from(err: TantivyError) -> Self {
+        Error::Index(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,255 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::index::SearchIndex;
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::{CODE_INDEX, PAGE_INDEX};
+
+/// File in the index directory with the name of the current generation
+pub const CURRENT_FILE: &str = "current";
+
+/// File in the index directory with the name of the generation the server serves
+pub const SERVING_FILE: &str = "serving";
+
+/// Subdirectory with the generations
+const GENERATIONS_DIR: &str = "generations";
+
+/// Generations of the indexes in the index directory.
+///
+/// Every indexing builds a new generation in `generations/{number}`, which becomes
+/// current once it's validated, by atomically replacing the `current` file. The
+/// previous generation is kept for a rollback, and the one the server serves until it
+/// reloads the indexes, as recorded in the `serving` file. Older ones are deleted.
+pub struct Generations {
+    index_dir: PathBuf,
+}
+
+impl Generations {
+    pub fn new(index_dir: &Path) -> Self {
+        Self {
+            index_dir: index_dir.to_path_buf(),
+        }
+    }
+
+    /// Name of the current generation, if any.
+    pub fn current(&self) -> io::Result<Option<String>> {
+        self.read_name(CURRENT_FILE)
+    }
+
+    /// Name of the generation the server serves, if any.
+    pub fn serving(&self) -> io::Result<Option<String>> {
+        self.read_name(SERVING_FILE)
+    }
+
+    /// Records the generation the server serves, so it isn't deleted before the server
+    /// reloads the indexes.
+    pub fn set_serving(&self, name: &str) -> io::Result<()> {
+        self.write_name(SERVING_FILE, name)
+    }
+
+    /// Directory with the current indexes.
+    pub fn current_dir(&self) -> io::Result<PathBuf> {
+        Ok(self.generation_dir(self.current()?.as_deref()))
+    }
+
+    /// Directory with the indexes of the generation. Indexes built before there were
+    /// generations are directly in the index directory.
+    pub fn generation_dir(&self, name: Option<&str>) -> PathBuf {
+        match name {
+            Some(name) => self.dir(name),
+            None => self.index_dir.clone(),
+        }
+    }
+
+    fn dir(&self, name: &str) -> PathBuf {
+        self.index_dir.join(GENERATIONS_DIR).join(name)
+    }
+
+    /// Numbers of the generations on disk, in ascending order.
+    fn list(&self) -> io::Result<Vec<u64>> {
+        let entries = match fs::read_dir(self.index_dir.join(GENERATIONS_DIR)) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+
+        let mut numbers: Vec<u64> = entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+            .collect();
+        numbers.sort();
+
+        Ok(numbers)
+    }
+
+    /// Creates the directory of a new generation. With `copy_current` it starts as
+    /// a copy of the current indexes, to be updated incrementally.
+    pub fn create(&self, copy_current: bool) -> io::Result<PathBuf> {
+        let number = self.list()?.last().map_or(1, |last| last + 1);
+        let dir = self.dir(&number.to_string());
+        fs::create_dir_all(&dir)?;
+
+        let current_dir = self.current_dir()?;
+
+        if copy_current && current_dir.join(MANIFEST_FILE).exists() {
+            for name in [PAGE_INDEX, CODE_INDEX] {
+                copy_dir(&current_dir.join(name), &dir.join(name))?;
+            }
+            fs::copy(current_dir.join(MANIFEST_FILE), dir.join(MANIFEST_FILE))?;
+        }
+
+        Ok(dir)
+    }
+
+    /// Checks the indexes of the generation can be opened and hold the documents
+    /// recorded in its manifest.
+    pub fn validate(&self, dir: &Path) -> io::Result<()> {
+        let manifest = Manifest::load(dir)?;
+
+        let pages: usize = manifest.sources.values().map(|s| s.pages.len()).sum();
+        let code_blocks: usize = manifest.sources.values().map(|s| s.code_blocks.len()).sum();
+
+        for (name, expected) in [(PAGE_INDEX, pages), (CODE_INDEX, code_blocks)] {
+            let index =
+                SearchIndex::open(&dir.join(name).to_string_lossy()).map_err(io::Error::other)?;
+
+            if index.num_docs() != expected as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "`{}` index of {:?} has {} documents, expected {}",
+                        name,
+                        dir,
+                        index.num_docs(),
+                        expected
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the generation current. The previously current one and the served one are
+    /// kept, older generations are deleted.
+    pub fn activate(&self, dir: &Path) -> io::Result<()> {
+        let name = dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid generation"))?;
+
+        let previous = self.current()?;
+        let serving = self.serving()?;
+        self.set_current(name)?;
+
+        for number in self.list()? {
+            let other = number.to_string();
+            if other != name
+                && Some(&other) != previous.as_ref()
+                && Some(&other) != serving.as_ref()
+            {
+                fs::remove_dir_all(self.dir(&other))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Makes the generation before the current one current again, returns its name.
+    pub fn rollback(&self) -> io::Result<String> {
+        let current: Option<u64> = self.current()?.and_then(|name| name.parse().ok());
+
+        let previous = self
+            .list()?
+            .into_iter()
+            .rfind(|number| current.is_some_and(|current| *number < current))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No previous generation"))?;
+
+        let name = previous.to_string();
+        self.set_current(&name)?;
+
+        Ok(name)
+    }
+
+    fn set_current(&self, name: &str) -> io::Result<()> {
+        self.write_name(CURRENT_FILE, name)
+    }
+
+    fn read_name(&self, file: &str) -> io::Result<Option<String>> {
+        match fs::read_to_string(self.index_dir.join(file)) {
+            Ok(name) => Ok(Some(name.trim().to_string())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Atomically replaces the file with the name of a generation.
+    fn write_name(&self, file: &str, name: &str) -> io::Result<()> {
+        let path = self.index_dir.join(file);
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, format!("{}\n", name))?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+/// Copies the files of a directory, ie. of a tantivy index.
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::indexer::Indexer;
+    use crate::tokenizer::AnalyzerConfig;
+
+    #[test]
+    fn test_generations() {
+        let index_dir = tempfile::tempdir().unwrap();
+        let index_dir = index_dir.path();
+        let generations = Generations::new(index_dir);
+
+        assert_eq!(generations.current().unwrap(), None);
+        assert_eq!(generations.current_dir().unwrap(), index_dir);
+
+        for expected in ["1", "2", "3"] {
+            let dir = generations.create(true).unwrap();
+            Indexer::open(&dir, &AnalyzerConfig::default())
+                .unwrap()
+                .commit()
+                .unwrap();
+            generations.validate(&dir).unwrap();
+            generations.activate(&dir).unwrap();
+
+            assert_eq!(generations.current().unwrap().as_deref(), Some(expected));
+        }
+
+        // The current and the previous generations are kept
+        assert_eq!(generations.list().unwrap(), vec![2, 3]);
+
+        // And the served one, until the server reloads
+        generations.set_serving("2").unwrap();
+        let dir = generations.create(true).unwrap();
+        generations.activate(&dir).unwrap();
+        assert_eq!(generations.list().unwrap(), vec![2, 3, 4]);
+
+        generations.set_serving("4").unwrap();
+        let dir = generations.create(true).unwrap();
+        generations.activate(&dir).unwrap();
+        assert_eq!(generations.list().unwrap(), vec![4, 5]);
+
+        assert_eq!(generations.rollback().unwrap(), "4");
+        assert_eq!(generations.current_dir().unwrap(), generations.dir("4"));
+        assert!(generations.rollback().is_err());
+    }
+}
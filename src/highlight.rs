@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use serde::ser::SerializeSeq;
+use serde::{Serialize, Serializer};
+use tantivy::tokenizer::TextAnalyzer;
+
+/// Highlighted parts of the text fields of a result, as byte ranges of the UTF-8 text,
+/// serialized as `[start, end]` pairs with the end excluded.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct Highlights {
+    #[serde(serialize_with = "serialize_ranges")]
+    pub title: Vec<Range<usize>>,
+    #[serde(serialize_with = "serialize_ranges")]
+    pub heading: Vec<Range<usize>>,
+    #[serde(serialize_with = "serialize_ranges")]
+    pub snippet: Vec<Range<usize>>,
+    #[serde(serialize_with = "serialize_ranges")]
+    pub body: Vec<Range<usize>>,
+}
+
+fn serialize_ranges<S: Serializer>(
+    ranges: &[Range<usize>],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut seq = serializer.serialize_seq(Some(ranges.len()))?;
+    for range in ranges {
+        seq.serialize_element(&[range.start, range.end])?;
+    }
+    seq.end()
+}
+
+/// Terms of the query words as the analyzer indexes them. Terms without letters or
+/// digits, like the `<` of `Vec<T>`, aren't highlighted.
+pub fn query_terms<'a>(
+    analyzer: &mut TextAnalyzer,
+    words: impl IntoIterator<Item = &'a String>,
+) -> HashSet<String> {
+    let mut terms = HashSet::new();
+
+    for word in words {
+        analyzer.token_stream(word).process(&mut |token| {
+            if token.text.chars().any(char::is_alphanumeric) {
+                terms.insert(token.text.clone());
+            }
+        });
+    }
+
+    terms
+}
+
+/// Byte ranges of the tokens of the text which are the terms, sorted and merged, so
+/// they don't overlap. Parts of compound tokens, like `Arc` of `std::sync::Arc`, are
+/// highlighted on their own.
+pub fn token_ranges(
+    analyzer: &mut TextAnalyzer,
+    text: &str,
+    terms: &HashSet<String>,
+) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+
+    if terms.is_empty() {
+        return ranges;
+    }
+
+    analyzer.token_stream(text).process(&mut |token| {
+        if terms.contains(&token.text) {
+            ranges.push(token.offset_from..token.offset_to);
+        }
+    });
+
+    merge(ranges)
+}
+
+/// Sorted ranges, the overlapping and adjacent ones merged.
+fn merge(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| (range.start, range.end));
+
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    merged
+}
+
+/// HTML of the text with the ranges in `<b>` tags, the text is escaped.
+pub fn to_html(text: &str, ranges: &[Range<usize>]) -> String {
+    let mut html = String::with_capacity(text.len() + ranges.len() * 7);
+    let mut end = 0;
+
+    for range in ranges {
+        html.push_str(&html_escape::encode_text(&text[end..range.start]));
+        html.push_str("<b>");
+        html.push_str(&html_escape::encode_text(&text[range.clone()]));
+        html.push_str("</b>");
+        end = range.end;
+    }
+    html.push_str(&html_escape::encode_text(&text[end..]));
+
+    html
+}
+
+/// Lines of a code body shown in its snippet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SnippetLines {
+    /// Number of the first line, from 1
+    pub start: usize,
+    /// Number of the lines
+    pub count: usize,
+    /// Lines before the snippet are left out
+    pub elided_before: bool,
+    /// Lines after the snippet are left out
+    pub elided_after: bool,
+}
+
+/// Window of at most `max_lines` lines of the code with the most distinct highlighted
+/// words, then with the most highlights, the first one of the best. Returns the text of
+/// the lines, their highlights and where they are in the code.
+pub fn code_snippet(
+    code: &str,
+    ranges: &[Range<usize>],
+    max_lines: usize,
+) -> (String, Vec<Range<usize>>, SnippetLines) {
+    // Byte offsets of the starts of the lines
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(code.match_indices('\n').map(|(i, _)| i + 1))
+        .filter(|&start| start < code.len())
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) - 1;
+
+    let window = max_lines.clamp(1, line_starts.len().max(1));
+    let mut best = (0, 0, 0);
+
+    for first in 0..=line_starts.len().saturating_sub(window) {
+        let in_window: Vec<&Range<usize>> = ranges
+            .iter()
+            .filter(|range| (first..first + window).contains(&line_of(range.start)))
+            .collect();
+        let distinct: HashSet<String> = in_window
+            .iter()
+            .map(|range| code[(*range).clone()].to_lowercase())
+            .collect();
+
+        if (distinct.len(), in_window.len()) > (best.1, best.2) {
+            best = (first, distinct.len(), in_window.len());
+        }
+    }
+
+    let first = best.0;
+    let start = line_starts.get(first).copied().unwrap_or(0);
+    let end = line_starts
+        .get(first + window)
+        .copied()
+        .unwrap_or(code.len());
+
+    let text = code[start..end].trim_end_matches('\n').to_string();
+    let snippet_ranges = ranges
+        .iter()
+        .filter(|range| range.start >= start && range.end <= start + text.len())
+        .map(|range| range.start - start..range.end - start)
+        .collect();
+
+    let lines = SnippetLines {
+        start: first + 1,
+        count: window.min(line_starts.len()),
+        elided_before: first > 0,
+        elided_after: first + window < line_starts.len(),
+    };
+
+    (text, snippet_ranges, lines)
+}
+
+/// Ranges of the text trimmed of the whitespace at its start.
+pub fn trim_start(text: &str, ranges: &[Range<usize>]) -> (String, Vec<Range<usize>>) {
+    let trimmed = text.trim_start();
+    let shift = text.len() - trimmed.len();
+
+    let ranges = ranges
+        .iter()
+        .filter(|range| range.end > shift)
+        .map(|range| range.start.saturating_sub(shift)..range.end - shift)
+        .collect();
+
+    (trimmed.to_string(), ranges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::rust_code_analyzer;
+
+    fn highlight(words: &[&str], code: &str) -> String {
+        let mut analyzer = rust_code_analyzer();
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+        let terms = query_terms(&mut analyzer, &words);
+        let ranges = token_ranges(&mut analyzer, code, &terms);
+        to_html(code, &ranges)
+    }
+
+    #[test]
+    fn test_highlight_tokens() {
+        assert_eq!(
+            highlight(&["b"], "let b = abc + cb;"),
+            "let <b>b</b> = abc + cb;"
+        );
+        assert_eq!(
+            highlight(&["Vec<T>"], "fn push(v: &mut Vec<T>) {}"),
+            "fn push(v: &amp;mut <b>Vec</b>&lt;<b>T</b>&gt;) {}"
+        );
+        assert_eq!(
+            highlight(&["arc"], "use std::sync::Arc;"),
+            "use std::sync::<b>Arc</b>;"
+        );
+        // The whole path and its parts are one range
+        assert_eq!(
+            highlight(&["std::sync::Arc", "sync"], "std::sync::Arc"),
+            "<b>std::sync::Arc</b>"
+        );
+        assert_eq!(highlight(&["<"], "a < b"), "a &lt; b");
+    }
+
+    #[test]
+    fn test_serialize_highlights() {
+        let highlights = Highlights {
+            title: vec![0..3, 5..9],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_string(&highlights).unwrap(),
+            r#"{"title":[[0,3],[5,9]],"heading":[],"snippet":[],"body":[]}"#
+        );
+        assert_eq!(
+            trim_start("  ab cd", &[2..4, 5..7]),
+            ("ab cd".to_string(), vec![0..2, 3..5])
+        );
+    }
+
+    #[test]
+    fn test_code_snippet() {
+        let code = "use std::rc::Rc;\n\nfn main() {\n    let a = Rc::new(1);\n    let b = Rc::clone(&a);\n    println!(\"{}\", b);\n}\n";
+        let ranges: Vec<_> = ["Rc", "clone"]
+            .iter()
+            .flat_map(|word| code.match_indices(word))
+            .map(|(i, word)| i..i + word.len())
+            .collect();
+        let (text, ranges, lines) = code_snippet(code, &merge(ranges), 2);
+
+        assert_eq!(text, "    let a = Rc::new(1);\n    let b = Rc::clone(&a);");
+        let highlighted: Vec<_> = ranges.iter().map(|range| &text[range.clone()]).collect();
+        assert_eq!(highlighted, ["Rc", "Rc", "clone"]);
+        assert_eq!(
+            lines,
+            SnippetLines {
+                start: 4,
+                count: 2,
+                elided_before: true,
+                elided_after: true,
+            }
+        );
+
+        // Short code is whole
+        let (text, ranges, lines) = code_snippet("let x = 1;\n", &[], 8);
+        assert_eq!(text, "let x = 1;");
+        assert!(ranges.is_empty());
+        assert_eq!((lines.start, lines.count), (1, 1));
+        assert!(!lines.elided_before && !lines.elided_after);
+    }
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(merge(vec![5..8, 0..3, 2..4, 8..9]), [0..4, 5..9]);
+    }
+}
@@ -1,9 +1,52 @@
-use serde::Serialize;
-use tantivy::collector::{Count, TopDocs};
-use tantivy::query::{FuzzyTermQuery, QueryParser};
-use tantivy::schema::{Field, Schema, Term, Value, STORED, TEXT};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tantivy::collector::{Count, DocSetCollector, FacetCollector, TopDocs};
+use tantivy::query::{
+    AllQuery, BooleanQuery, BoostQuery, EmptyQuery, FuzzyTermQuery, Occur, Query, QueryParser,
+    QueryParserError, TermQuery,
+};
+use tantivy::schema::{
+    Facet, Field, FieldType, IndexRecordOption, Schema, Term, TextFieldIndexing, TextOptions,
+    Value, FAST, INDEXED, STORED, STRING, TEXT,
+};
 use tantivy::snippet::SnippetGenerator;
-use tantivy::{doc, Index, IndexWriter, ReloadPolicy, Searcher, TantivyDocument, TantivyError};
+use tantivy::{
+    doc, DateTime, DocAddress, Index, IndexWriter, ReloadPolicy, Searcher, TantivyDocument,
+    TantivyError,
+};
+
+use crate::error::Error;
+use crate::highlight::{self, Highlights, SnippetLines};
+use crate::query::{quote, Filter, FilterField, UserQuery};
+use crate::tokenizer::{
+    register_tokenizers, AnalyzerConfig, EXACT_TOKENIZER, PROSE_TOKENIZER, RUST_CODE_TOKENIZER,
+};
+
+/// Version of the schema, bumped when fields are added, removed or changed. Indexes of
+/// other versions have to be migrated before they can be opened.
+pub const SCHEMA_VERSION: u32 = 4;
+
+/// Boost of the unstemmed shadow fields, exact forms of words outrank other forms
+const EXACT_BOOST: f32 = 1.5;
+
+/// Score of a typo match, below the scores of exact matches
+const FUZZY_BOOST: f32 = 0.5;
+
+/// Clauses of a boolean query
+pub type Clauses = Vec<(Occur, Box<dyn Query>)>;
+
+/// Kind of the documents in the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// Pages with prose in the body
+    Page,
+    /// Code blocks with Rust code in the body, analyzed by the Rust tokenizer
+    Code,
+}
 
 pub struct SearchIndex {
     dir: String,
@@ -12,6 +55,10 @@ pub struct SearchIndex {
     searcher: Option<Searcher>,
     index_writer: Option<IndexWriter>,
     query_parser: Option<QueryParser>,
+    /// None for indexes built before the analyzer was stored with them
+    analyzer: Option<AnalyzerConfig>,
+    /// None for indexes built before the version was stored with them
+    schema_version: Option<u32>,
     // flags
     skip_snippet: bool,
     return_body: bool,
@@ -19,16 +66,156 @@ pub struct SearchIndex {
 
 #[derive(Debug, Default, Serialize)]
 pub struct SearchResult {
+    /// Stable id of the document
+    pub id: String,
     pub url: String,
+    /// Name of the source, ie. `nomicon`
+    pub source: String,
+    /// Title of the book
+    pub book: String,
+    /// Chapter title
     pub title: String,
+    /// Titles of the chapter and its parents in the summary, from the top level one
+    pub chapter_path: Vec<String>,
+    /// Depth of the chapter in the summary, 1 for the top level chapters
+    pub depth: u64,
+    /// Section heading, if the hit is a section of the chapter
+    pub heading: Option<String>,
+    /// Language of a code block
+    pub lang: Option<String>,
+    /// Rust edition of a code block, if it's set
+    pub edition: Option<u16>,
+    pub word_count: u64,
+    /// Last modification of the file, in seconds since the Unix epoch
+    pub modified: Option<i64>,
     pub snippet: Option<String>,
+    /// Lines of the code in the snippet of a code block
+    pub snippet_lines: Option<SnippetLines>,
     pub body: Option<String>,
+    /// Highlighted words of the title, heading, snippet and body
+    pub highlights: Highlights,
+    /// Matching code blocks of the chapter, when the results are grouped by chapters
+    pub code_blocks: Vec<SearchResult>,
+    /// Tantivy score of the hit
+    pub score: f32,
+    /// Address of the document within its index
+    #[serde(skip)]
+    pub doc_address: Option<DocAddress>,
+}
+
+impl SearchResult {
+    /// Sets the snippet to the lines of the body with the most highlights, at most
+    /// `max_lines` of them.
+    pub fn set_code_snippet(&mut self, max_lines: usize) {
+        let Some(body) = &self.body else {
+            return;
+        };
+
+        let (snippet, highlights, lines) =
+            highlight::code_snippet(body, &self.highlights.body, max_lines);
+        self.snippet = Some(snippet).filter(|snippet| !snippet.is_empty());
+        self.snippet_lines = self.snippet.is_some().then_some(lines);
+        self.highlights.snippet = highlights;
+    }
+
+    /// The result with its text fields as HTML, escaped and with the highlighted words
+    /// in `<b>` tags. The highlights are left out, they are ranges of the plain text.
+    pub fn into_html(self) -> Self {
+        let highlights = &self.highlights;
+        let html = |text: &str, ranges: &[Range<usize>]| highlight::to_html(text, ranges);
+
+        SearchResult {
+            title: html(&self.title, &highlights.title),
+            heading: self
+                .heading
+                .as_deref()
+                .map(|heading| html(heading, &highlights.heading)),
+            snippet: self
+                .snippet
+                .as_deref()
+                .map(|snippet| html(snippet, &highlights.snippet)),
+            body: self
+                .body
+                .as_deref()
+                .map(|body| html(body, &highlights.body)),
+            highlights: Highlights::default(),
+            code_blocks: self
+                .code_blocks
+                .into_iter()
+                .map(SearchResult::into_html)
+                .collect(),
+            ..self
+        }
+    }
+}
+
+/// A document to be added to an index.
+#[derive(Debug, Default, Clone)]
+pub struct IndexDocument {
+    /// Stable id of the document, the url plus the ordinal of a code block
+    pub id: String,
+    /// Hash of the indexed content, to detect changed documents
+    pub hash: String,
+    pub url: String,
+    /// Name of the source, ie. `nomicon`
+    pub source: String,
+    /// Title of the book
+    pub book: String,
+    pub title: String,
+    /// Titles of the chapter and its parents in the summary, from the top level one
+    pub chapter_path: Vec<String>,
+    pub heading: String,
+    pub body: String,
+    /// Language of a code block
+    pub lang: Option<String>,
+    /// Rust edition of a code block
+    pub edition: Option<u16>,
+    /// Last modification of the file, in seconds since the Unix epoch
+    pub modified: Option<i64>,
+}
+
+/// Settings stored in the commits of the index.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct IndexPayload {
+    schema_version: Option<u32>,
+    analyzer: AnalyzerConfig,
+}
+
+/// A page of search results.
+#[derive(Debug, Default)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    /// Number of all matching documents, or of their chapters when they are grouped
+    pub total: usize,
+    /// More documents matched than can be paged to, `total` counts the ones which can
+    pub truncated: bool,
+    /// Rewrites of the query which were searched too
+    pub expansions: Vec<String>,
+    /// Parts of the query which were ignored
+    pub ignored: Vec<String>,
+    /// Number of matching documents in every book, regardless of the book filters
+    pub books: Vec<BookCount>,
+    /// Query with the misspelled words corrected
+    pub suggestion: Option<String>,
+    /// The results are of the suggestion, the query had none
+    pub autocorrected: bool,
+}
+
+/// Number of documents of a book matching a query.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookCount {
+    /// Name of the source, ie. `nomicon`
+    pub name: String,
+    pub title: String,
+    pub count: usize,
 }
 
 impl SearchIndex {
-    pub fn create(dir: &str) -> Result<Self, TantivyError> {
-        let schema = SearchIndex::create_schema();
+    pub fn create(dir: &str, kind: IndexKind, analyzer: &AnalyzerConfig) -> Result<Self, Error> {
+        let schema = SearchIndex::create_schema(kind, analyzer);
         let index = Index::create_in_dir(dir, schema)?;
+        register_tokenizers(&index, analyzer);
         let index_writer = index.writer(50_000_000)?;
 
         println!("Created `{dir}` directory");
@@ -41,14 +228,69 @@ impl SearchIndex {
             searcher: None,
             index_writer: Some(index_writer),
             query_parser: None,
+            analyzer: Some(analyzer.clone()),
+            schema_version: Some(SCHEMA_VERSION),
         })
     }
 
-    pub fn open(dir: &str) -> Result<Self, TantivyError> {
-        let index = Index::open_in_dir(dir)?;
+    /// Opens an existing index for adding and deleting documents.
+    pub fn open_for_update(dir: &str) -> Result<Self, Error> {
+        let mut search_index = SearchIndex::open(dir)?;
+        search_index.index_writer = Some(search_index.index.writer(50_000_000)?);
+
+        Ok(search_index)
+    }
+
+    /// Opens the index with the analyzer it was built with. Fails when the index was
+    /// built with another version of the schema, or its fields aren't the ones of the
+    /// version.
+    pub fn open(dir: &str) -> Result<Self, Error> {
+        let search_index = SearchIndex::open_any_version(dir)?;
+
+        if search_index.schema_version != Some(SCHEMA_VERSION) {
+            return Err(Error::SchemaVersion {
+                dir: dir.to_string(),
+                found: search_index.schema_version,
+                expected: SCHEMA_VERSION,
+            });
+        }
+
+        let analyzer = search_index.analyzer.clone().unwrap_or_default();
+        if !search_index.has_schema(IndexKind::Page, &analyzer)
+            && !search_index.has_schema(IndexKind::Code, &analyzer)
+        {
+            return Err(Error::SchemaMismatch(dir.to_string()));
+        }
+
+        Ok(search_index)
+    }
+
+    /// Opens the index whatever version of the schema it was built with, to read its
+    /// stored documents when it's migrated. Searching it may fail.
+    pub fn open_any_version(dir: &str) -> Result<Self, Error> {
+        if !Path::new(dir).join("meta.json").exists() {
+            return Err(Error::MissingIndex(dir.to_string()));
+        }
+
+        let corrupt = |err: TantivyError| Error::CorruptIndex {
+            dir: dir.to_string(),
+            message: err.to_string(),
+        };
+
+        let index = Index::open_in_dir(dir).map_err(corrupt)?;
+
+        let payload = index
+            .load_metas()
+            .map_err(corrupt)?
+            .payload
+            .and_then(|payload| serde_json::from_str::<IndexPayload>(&payload).ok());
+        let analyzer = payload.as_ref().map(|payload| payload.analyzer.clone());
+        let schema_version = payload.and_then(|payload| payload.schema_version);
+        register_tokenizers(&index, &analyzer.clone().unwrap_or_default());
+
         println!("Opened `{dir}` index");
 
-        let (searcher, query_parser) = SearchIndex::create_searcher(&index);
+        let (searcher, query_parser) = SearchIndex::create_searcher(&index).map_err(corrupt)?;
 
         Ok(Self {
             dir: dir.to_string(),
@@ -58,76 +300,343 @@ impl SearchIndex {
             searcher: Some(searcher),
             query_parser: Some(query_parser),
             index_writer: None,
+            analyzer,
+            schema_version,
         })
     }
 
-    fn create_schema() -> Schema {
+    fn create_schema(kind: IndexKind, analyzer: &AnalyzerConfig) -> Schema {
+        let analyzed = |tokenizer| {
+            TextFieldIndexing::default()
+                .set_tokenizer(tokenizer)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions)
+        };
+        let prose = TextOptions::default()
+            .set_stored()
+            .set_indexing_options(analyzed(PROSE_TOKENIZER));
+
+        let (title_options, body_options) = match kind {
+            IndexKind::Page => (prose.clone(), prose.clone()),
+            IndexKind::Code => (
+                TEXT | STORED,
+                TextOptions::default()
+                    .set_stored()
+                    .set_indexing_options(analyzed(RUST_CODE_TOKENIZER)),
+            ),
+        };
+
+        // Columnar too, so the titles are completed without loading the documents
+        let fast_title_options = title_options.clone().set_fast(None);
+
         let mut schema_builder = Schema::builder();
-        schema_builder.add_text_field("title", TEXT | STORED);
+        schema_builder.add_text_field("id", STRING | STORED);
+        schema_builder.add_text_field("hash", STRING | STORED);
+        schema_builder.add_text_field("title", fast_title_options.clone());
         schema_builder.add_text_field("url", TEXT | STORED);
-        schema_builder.add_text_field("body", TEXT | STORED);
+        // Columnar, so the hits are grouped by chapter without loading them
+        schema_builder.add_text_field("chapter_url", STRING | FAST);
+        schema_builder.add_text_field("heading", fast_title_options);
+        schema_builder.add_text_field("body", body_options);
+        schema_builder.add_facet_field("source", STORED);
+        schema_builder.add_text_field("book", title_options);
+        schema_builder.add_facet_field("chapter", STORED);
+        schema_builder.add_u64_field("depth", INDEXED | STORED | FAST);
+        schema_builder.add_text_field("lang", STRING | STORED | FAST);
+        schema_builder.add_u64_field("edition", INDEXED | STORED | FAST);
+        schema_builder.add_u64_field("word_count", STORED | FAST);
+        schema_builder.add_date_field("modified", INDEXED | STORED | FAST);
+
+        if kind == IndexKind::Page && analyzer.exact_fields {
+            let exact = TextOptions::default().set_indexing_options(analyzed(EXACT_TOKENIZER));
+            schema_builder.add_text_field("title_exact", exact.clone());
+            schema_builder.add_text_field("body_exact", exact);
+        }
 
         schema_builder.build()
     }
 
-    fn create_searcher(index: &Index) -> (Searcher, QueryParser) {
+    fn create_searcher(index: &Index) -> Result<(Searcher, QueryParser), TantivyError> {
         let reader = index
             .reader_builder()
             .reload_policy(ReloadPolicy::Manual)
-            .try_into()
-            .unwrap();
+            .try_into()?;
 
         let searcher = reader.searcher();
 
-        let mut query_parser = QueryParser::for_index(
-            index,
-            vec![
-                index.schema().get_field("url").unwrap(),
-                index.schema().get_field("title").unwrap(),
-                index.schema().get_field("body").unwrap(),
-            ],
-        );
+        let schema = index.schema();
+        // Indexes built before the book field was added are still searchable, until
+        // they are rebuilt
+        let mut fields: Vec<Field> = ["url", "title", "heading", "body", "book"]
+            .iter()
+            .filter_map(|name| schema.get_field(name).ok())
+            .collect();
+        let exact_fields: Vec<Field> = ["title_exact", "body_exact"]
+            .iter()
+            .filter_map(|name| schema.get_field(name).ok())
+            .collect();
+        fields.extend(&exact_fields);
+
+        let mut query_parser = QueryParser::for_index(index, fields);
 
         query_parser.set_conjunction_by_default();
+        for field in exact_fields {
+            query_parser.set_field_boost(field, EXACT_BOOST);
+        }
+
+        Ok((searcher, query_parser))
+    }
+
+    /// Whether the index has the schema of the kind of indexes built with the analyzer.
+    pub fn has_schema(&self, kind: IndexKind, analyzer: &AnalyzerConfig) -> bool {
+        self.index.schema() == SearchIndex::create_schema(kind, analyzer)
+    }
+
+    /// Field of the schema, fails when the index doesn't have it.
+    fn field(&self, name: &str) -> Result<Field, Error> {
+        self.index
+            .schema()
+            .get_field(name)
+            .map_err(|_| Error::SchemaMismatch(self.dir.clone()))
+    }
+
+    /// Unstemmed shadow of the field, if the index has them
+    fn exact(&self, name: &str) -> Option<Field> {
+        self.index
+            .schema()
+            .get_field(&format!("{}_exact", name))
+            .ok()
+    }
+
+    /// Highlights the words in the title, heading and body of the result, where the
+    /// fields have their tokens.
+    pub fn highlight(&self, result: &mut SearchResult, words: &[&String]) -> Result<(), Error> {
+        result.highlights.title = self.highlight_field("title", &result.title, words)?;
+        if let Some(heading) = &result.heading {
+            result.highlights.heading = self.highlight_field("heading", heading, words)?;
+        }
+        if let Some(body) = &result.body {
+            result.highlights.body = self.highlight_field("body", body, words)?;
+        }
+
+        Ok(())
+    }
+
+    fn highlight_field(
+        &self,
+        name: &str,
+        text: &str,
+        words: &[&String],
+    ) -> Result<Vec<Range<usize>>, Error> {
+        let mut analyzer = self.index.tokenizer_for_field(self.field(name)?)?;
+        let terms = highlight::query_terms(&mut analyzer, words.iter().copied());
+
+        Ok(highlight::token_ranges(&mut analyzer, text, &terms))
+    }
+
+    /// Whether the terms of the field are stems of the words.
+    fn is_stemmed(&self, field: Field) -> bool {
+        let stemming = self
+            .analyzer
+            .as_ref()
+            .map_or(AnalyzerConfig::default().stemming, |analyzer| {
+                analyzer.stemming
+            });
+
+        match self.index.schema().get_field_entry(field).field_type() {
+            FieldType::Str(options) => {
+                stemming
+                    && options
+                        .get_indexing_options()
+                        .is_some_and(|indexing| indexing.tokenizer() == PROSE_TOKENIZER)
+            }
+            _ => false,
+        }
+    }
+
+    /// Analyzer the index was built with, if it's known.
+    pub fn analyzer(&self) -> Option<&AnalyzerConfig> {
+        self.analyzer.as_ref()
+    }
+
+    /// Version of the schema the index was built with, if it's known.
+    pub fn schema_version(&self) -> Option<u32> {
+        self.schema_version
+    }
 
-        (searcher, query_parser)
+    /// Stored text of the field, None if the index has no such field.
+    fn stored_str(&self, doc: &TantivyDocument, name: &str) -> Option<String> {
+        let field = self.index.schema().get_field(name).ok()?;
+        doc.get_first(field)?
+            .as_str()
+            .map(|value| value.to_string())
     }
 
-    fn url(&self) -> Field {
-        self.index.schema().get_field("url").unwrap()
+    fn stored_u64(&self, doc: &TantivyDocument, name: &str) -> Option<u64> {
+        let field = self.index.schema().get_field(name).ok()?;
+        doc.get_first(field)?.as_u64()
     }
 
-    fn title(&self) -> Field {
-        self.index.schema().get_field("title").unwrap()
+    /// Stored date of the field, in seconds since the Unix epoch.
+    fn stored_date(&self, doc: &TantivyDocument, name: &str) -> Option<i64> {
+        let field = self.index.schema().get_field(name).ok()?;
+        let date = doc.get_first(field)?.as_datetime()?;
+        Some(date.into_timestamp_secs())
     }
 
-    fn body(&self) -> Field {
-        self.index.schema().get_field("body").unwrap()
+    /// Segments of the stored facet of the field, empty if there is none.
+    fn stored_facet(&self, doc: &TantivyDocument, name: &str) -> Vec<String> {
+        let Ok(field) = self.index.schema().get_field(name) else {
+            return vec![];
+        };
+
+        doc.get_first(field)
+            .and_then(|value| value.as_facet())
+            .and_then(|facet| Facet::from_encoded(facet.as_bytes().to_vec()).ok())
+            .map(|facet| {
+                facet
+                    .to_path()
+                    .into_iter()
+                    .map(|segment| segment.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// All documents of the index, as they are stored. Fields missing from the schema
+    /// of the index are left empty.
+    pub fn stored_documents(&self) -> Result<Vec<IndexDocument>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(vec![]);
+        };
+
+        let mut doc_addresses: Vec<DocAddress> = searcher
+            .search(&AllQuery, &DocSetCollector)?
+            .into_iter()
+            .collect();
+        doc_addresses.sort();
+
+        doc_addresses
+            .into_iter()
+            .map(|doc_address| {
+                let doc = searcher.doc::<TantivyDocument>(doc_address)?;
+
+                Ok(IndexDocument {
+                    id: self.stored_str(&doc, "id").unwrap_or_default(),
+                    hash: self.stored_str(&doc, "hash").unwrap_or_default(),
+                    url: self.stored_str(&doc, "url").unwrap_or_default(),
+                    source: self.stored_facet(&doc, "source").concat(),
+                    book: self.stored_str(&doc, "book").unwrap_or_default(),
+                    title: self.stored_str(&doc, "title").unwrap_or_default(),
+                    chapter_path: self.stored_facet(&doc, "chapter"),
+                    heading: self.stored_str(&doc, "heading").unwrap_or_default(),
+                    body: self.stored_str(&doc, "body").unwrap_or_default(),
+                    lang: self.stored_str(&doc, "lang"),
+                    edition: self
+                        .stored_u64(&doc, "edition")
+                        .map(|edition| edition as u16),
+                    modified: self.stored_date(&doc, "modified"),
+                })
+            })
+            .collect()
+    }
+
+    pub fn add_document(&mut self, document: IndexDocument) -> Result<u64, Error> {
+        let mut tantivy_doc = doc!(
+            self.field("id")? => document.id,
+            self.field("hash")? => document.hash,
+            self.field("url")? => document.url,
+        );
+
+        if let Some(title_exact) = self.exact("title") {
+            tantivy_doc.add_text(title_exact, &document.title);
+        }
+        if let Some(body_exact) = self.exact("body") {
+            tantivy_doc.add_text(body_exact, &document.body);
+        }
+
+        let word_count = document.body.split_whitespace().count() as u64;
+
+        tantivy_doc.add_text(self.field("chapter_url")?, chapter_url(&document.url));
+        tantivy_doc.add_facet(self.field("source")?, Facet::from_path([document.source]));
+        tantivy_doc.add_text(self.field("book")?, document.book);
+        tantivy_doc.add_facet(
+            self.field("chapter")?,
+            Facet::from_path(&document.chapter_path),
+        );
+        tantivy_doc.add_u64(self.field("depth")?, document.chapter_path.len() as u64);
+        if let Some(lang) = document.lang {
+            tantivy_doc.add_text(self.field("lang")?, lang);
+        }
+        if let Some(edition) = document.edition {
+            tantivy_doc.add_u64(self.field("edition")?, edition.into());
+        }
+        tantivy_doc.add_u64(self.field("word_count")?, word_count);
+        if let Some(modified) = document.modified {
+            tantivy_doc.add_date(
+                self.field("modified")?,
+                DateTime::from_timestamp_secs(modified),
+            );
+        }
+
+        tantivy_doc.add_text(self.field("title")?, document.title);
+        tantivy_doc.add_text(self.field("heading")?, document.heading);
+        tantivy_doc.add_text(self.field("body")?, document.body);
+
+        Ok(self.writer()?.add_document(tantivy_doc)?)
     }
 
-    pub fn add_document(
-        &mut self,
-        url: String,
-        title: String,
-        body: String,
-    ) -> Result<u64, TantivyError> {
-        self.index_writer.as_ref().unwrap().add_document(doc!(
-            self.url() => url,
-            self.title() => title,
-            self.body() => body,
-        ))
+    /// Deletes the document with the id, the deletion is applied at the next commit.
+    pub fn delete_document(&mut self, id: &str) -> Result<u64, Error> {
+        let term = Term::from_field_text(self.field("id")?, id);
+        Ok(self.writer()?.delete_term(term))
     }
 
-    pub fn commit(&mut self) -> Result<u64, TantivyError> {
-        self.index_writer.as_mut().unwrap().commit()?;
+    /// Commits the documents, along with the settings of the index.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        let payload = match &self.analyzer {
+            Some(analyzer) => Some(
+                serde_json::to_string(&IndexPayload {
+                    schema_version: Some(SCHEMA_VERSION),
+                    analyzer: analyzer.clone(),
+                })
+                .map_err(std::io::Error::other)?,
+            ),
+            None => None,
+        };
+
+        let mut prepared_commit = self.writer()?.prepare_commit()?;
+        if let Some(payload) = payload {
+            prepared_commit.set_payload(&payload);
+        }
+
+        prepared_commit.commit()?;
         println!("Commited `{}` index", self.dir);
 
-        let (searcher, query_parser) = SearchIndex::create_searcher(&self.index);
+        let (searcher, query_parser) = SearchIndex::create_searcher(&self.index)?;
 
         self.searcher = Some(searcher);
         self.query_parser = Some(query_parser);
 
-        Ok(0)
+        Ok(())
+    }
+
+    /// Writer of the index, which only the indexes opened for writing have.
+    fn writer(&mut self) -> Result<&mut IndexWriter, Error> {
+        self.index_writer
+            .as_mut()
+            .ok_or_else(|| Error::ReadOnly(self.dir.clone()))
+    }
+
+    pub fn num_docs(&self) -> u64 {
+        self.searcher
+            .as_ref()
+            .map_or(0, |searcher| searcher.num_docs())
+    }
+
+    pub fn num_segments(&self) -> usize {
+        self.searcher
+            .as_ref()
+            .map_or(0, |searcher| searcher.segment_readers().len())
     }
 
     pub fn set_skip_snippet(&mut self) {
@@ -138,134 +647,667 @@ impl SearchIndex {
         self.return_body = true;
     }
 
-    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>, TantivyError> {
-        let mut results = Vec::new();
+    /// Parses a query in tantivy's syntax against url, title, heading and body, and
+    /// their unstemmed forms.
+    pub fn parse_query(&self, query: &str) -> Result<Box<dyn Query>, Error> {
+        match &self.query_parser {
+            Some(query_parser) => {
+                query_parser
+                    .parse_query(query)
+                    .map_err(|err| Error::QuerySyntax {
+                        position: syntax_error_position(query, &err),
+                        message: err.to_string(),
+                    })
+            }
+            None => Err(TantivyError::InvalidArgument(format!(
+                "`{}` index isn't open for searching",
+                self.dir
+            ))
+            .into()),
+        }
+    }
+
+    /// Parses a query in tantivy's syntax leniently, the parts which can't be parsed
+    /// are left out and described in the returned messages.
+    pub fn parse_query_lenient(&self, query: &str) -> (Box<dyn Query>, Vec<String>) {
+        match &self.query_parser {
+            Some(query_parser) => {
+                let (query, errors) = query_parser.parse_query_lenient(query);
+                (query, errors.iter().map(|err| err.to_string()).collect())
+            }
+            None => (Box::new(EmptyQuery), vec![]),
+        }
+    }
+
+    /// Clauses restricting the documents to the filters of the query and leaving out
+    /// its excluded words, along with the messages of the parts which can't be parsed.
+    ///
+    /// Every title filter must match, any of the books and languages.
+    pub fn filter_clauses(&self, query: &UserQuery) -> Result<(Clauses, Vec<String>), Error> {
+        let mut clauses = vec![];
+        let mut errors = vec![];
 
-        if let Some(query_parser) = &self.query_parser {
-            if let Ok(query) = query_parser.parse_query(query) {
-                if let Some(searcher) = &self.searcher {
-                    if let Ok(docs) = searcher.search(&query, &TopDocs::with_limit(50)) {
-                        if let Ok(mut snippet_generator) =
-                            SnippetGenerator::create(searcher, &query, self.body())
-                        {
-                            snippet_generator.set_max_num_chars(80);
-
-                            for (_score, doc_address) in docs {
-                                if let Ok(retrieved_doc) =
-                                    searcher.doc::<TantivyDocument>(doc_address)
-                                {
-                                    let url = retrieved_doc
-                                        .get_first(self.url())
-                                        .unwrap()
-                                        .as_str()
-                                        .unwrap()
-                                        .to_string();
-
-                                    let title = retrieved_doc
-                                        .get_first(self.title())
-                                        .unwrap()
-                                        .as_str()
-                                        .unwrap()
-                                        .to_string();
-
-                                    let body = if self.return_body {
-                                        Some(
-                                            retrieved_doc
-                                                .get_first(self.body())
-                                                .unwrap()
-                                                .as_str()
-                                                .unwrap()
-                                                .to_string(),
-                                        )
-                                    } else {
-                                        None
-                                    };
-
-                                    let snippet = if !self.skip_snippet {
-                                        Some(
-                                            snippet_generator
-                                                .snippet_from_doc(&retrieved_doc)
-                                                .to_html()
-                                                .replace('\n', " ")
-                                                .trim()
-                                                .to_string(),
-                                        )
-                                    } else {
-                                        None
-                                    };
-
-                                    results.push(SearchResult {
-                                        title,
-                                        url,
-                                        snippet,
-                                        body,
-                                    });
-                                }
-                            }
-                        }
-                    }
+        for field in [FilterField::Title, FilterField::Book, FilterField::Lang] {
+            let mut any_of = vec![];
+
+            for filter in query.filters(field) {
+                let filter_query = self.filter_query(filter, &mut errors)?;
+                match (filter.negated, field) {
+                    (true, _) => clauses.push((Occur::MustNot, filter_query)),
+                    (false, FilterField::Title) => clauses.push((Occur::Must, filter_query)),
+                    (false, _) => any_of.push(filter_query),
                 }
             }
+
+            if !any_of.is_empty() {
+                clauses.push((Occur::Must, Box::new(BooleanQuery::union(any_of))));
+            }
         }
-        Ok(results)
+
+        for excluded in &query.excluded {
+            let (excluded_query, excluded_errors) = self.parse_query_lenient(&quote(excluded));
+            clauses.push((Occur::MustNot, excluded_query));
+            errors.extend(excluded_errors);
+        }
+
+        Ok((clauses, errors))
+    }
+
+    fn filter_query(
+        &self,
+        filter: &Filter,
+        errors: &mut Vec<String>,
+    ) -> Result<Box<dyn Query>, Error> {
+        Ok(match filter.field {
+            FilterField::Title => {
+                let title = quote(&filter.value);
+                let (title_query, title_errors) =
+                    self.parse_query_lenient(&format!("title:{0} OR heading:{0}", title));
+                errors.extend(title_errors);
+                title_query
+            }
+            FilterField::Book => Box::new(TermQuery::new(
+                Term::from_facet(self.field("source")?, &Facet::from_path([&filter.value])),
+                IndexRecordOption::Basic,
+            )),
+            FilterField::Lang => Box::new(TermQuery::new(
+                Term::from_field_text(self.field("lang")?, &filter.value),
+                IndexRecordOption::Basic,
+            )),
+        })
+    }
+
+    /// Typo tolerant query of the title, of its unstemmed form if there is one.
+    pub fn fuzzy_query_title(&self, query: &str) -> Result<Box<dyn Query>, Error> {
+        let field = match self.exact("title") {
+            Some(field) => field,
+            None => self.field("title")?,
+        };
+        self.fuzzy_query(query, field)
+    }
+
+    /// Typo tolerant query of the body, of its unstemmed form if there is one.
+    pub fn fuzzy_query_body(&self, query: &str) -> Result<Box<dyn Query>, Error> {
+        let field = match self.exact("body") {
+            Some(field) => field,
+            None => self.field("body")?,
+        };
+        self.fuzzy_query(query, field)
+    }
+
+    /// Every token of the query, as analyzed for the field, within its edit distance.
+    /// The exact term of a token is scored too, so typos rank below exact matches.
+    fn fuzzy_query(&self, query: &str, field: Field) -> Result<Box<dyn Query>, Error> {
+        let mut analyzer = self.index.tokenizer_for_field(field)?;
+        let mut tokens = Vec::new();
+        analyzer
+            .token_stream(query)
+            .process(&mut |token| tokens.push(token.text.clone()));
+
+        if tokens.is_empty() {
+            return Ok(Box::new(EmptyQuery));
+        }
+
+        let clauses = tokens
+            .iter()
+            .map(|token| {
+                let term = Term::from_field_text(field, token);
+                let exact = TermQuery::new(term.clone(), IndexRecordOption::WithFreqs);
+                let token_query: Box<dyn Query> = match fuzzy_distance(token) {
+                    0 => Box::new(exact),
+                    distance => Box::new(BooleanQuery::union(vec![
+                        Box::new(exact),
+                        Box::new(BoostQuery::new(
+                            Box::new(FuzzyTermQuery::new(term, distance, true)),
+                            FUZZY_BOOST,
+                        )),
+                    ])),
+                };
+                (Occur::Must, token_query)
+            })
+            .collect();
+
+        Ok(Box::new(BooleanQuery::new(clauses)))
+    }
+
+    /// Parses and runs the query, returning `limit` hits starting at `offset`.
+    pub fn search(&self, query: &str, offset: usize, limit: usize) -> Result<SearchPage, Error> {
+        let query = self.parse_query(query)?;
+        self.search_query(query.as_ref(), offset, limit)
     }
 
-    pub fn fuzzy_search_title(&self, query: &str) -> Result<Vec<SearchResult>, TantivyError> {
-        self.fuzzy_search(query, &self.title())
+    /// Runs the query, returning `limit` hits starting at `offset` and the total number
+    /// of matching documents.
+    pub fn search_query(
+        &self,
+        query: &dyn Query,
+        offset: usize,
+        limit: usize,
+    ) -> Result<SearchPage, Error> {
+        let (docs, total) = self.top_docs(query, offset, limit)?;
+
+        Ok(SearchPage {
+            results: self.results(query, &docs)?,
+            total,
+            ..Default::default()
+        })
     }
 
-    pub fn fuzzy_search_body(&self, query: &str) -> Result<Vec<SearchResult>, TantivyError> {
-        self.fuzzy_search(query, &self.body())
+    /// Scores and addresses of `limit` hits of the query starting at `offset`, without
+    /// loading them, and the total number of matching documents.
+    pub fn top_docs(
+        &self,
+        query: &dyn Query,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<(f32, DocAddress)>, usize), Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok((vec![], 0));
+        };
+
+        let (docs, total) = searcher.search(
+            query,
+            &(TopDocs::with_limit(limit.max(1)).and_offset(offset), Count),
+        )?;
+
+        match limit {
+            0 => Ok((vec![], total)),
+            _ => Ok((docs, total)),
+        }
     }
 
-    fn fuzzy_search(&self, query: &str, field: &Field) -> Result<Vec<SearchResult>, TantivyError> {
+    /// Loads the hits of the query, with snippets of the matches of its terms.
+    pub fn results(
+        &self,
+        query: &dyn Query,
+        docs: &[(f32, DocAddress)],
+    ) -> Result<Vec<SearchResult>, Error> {
         let mut results = Vec::new();
 
-        let term = Term::from_field_text(*field, query);
-        let query = FuzzyTermQuery::new(term, 1, true);
-
-        if let Some(searcher) = &self.searcher {
-            if let Ok((docs, _count)) = searcher.search(&query, &(TopDocs::with_limit(10), Count)) {
-                for (_score, doc_address) in docs {
-                    if let Ok(retrieved_doc) = searcher.doc::<TantivyDocument>(doc_address) {
-                        let url = retrieved_doc
-                            .get_first(self.url())
-                            .unwrap()
-                            .as_str()
-                            .unwrap()
-                            .to_string();
-
-                        let title = retrieved_doc
-                            .get_first(self.title())
-                            .unwrap()
-                            .as_str()
-                            .unwrap()
-                            .to_string();
-
-                        let body = if self.return_body {
-                            Some(
-                                retrieved_doc
-                                    .get_first(self.body())
-                                    .unwrap()
-                                    .as_str()
-                                    .unwrap()
-                                    .to_string(),
-                            )
-                        } else {
-                            None
-                        };
-
-                        results.push(SearchResult {
-                            title,
-                            url,
-                            body,
-                            ..Default::default()
-                        });
-                    }
-                }
+        let Some(searcher) = &self.searcher else {
+            return Ok(results);
+        };
+
+        if docs.is_empty() {
+            return Ok(results);
+        }
+
+        let snippet_generator = match self.skip_snippet {
+            true => None,
+            false => {
+                let mut snippet_generator =
+                    SnippetGenerator::create(searcher, query, self.field("body")?)?;
+                snippet_generator.set_max_num_chars(80);
+                Some(snippet_generator)
             }
+        };
+
+        for &(score, doc_address) in docs {
+            let retrieved_doc = searcher.doc::<TantivyDocument>(doc_address)?;
+
+            let stored = |name| self.stored_str(&retrieved_doc, name);
+            let stored_u64 = |name| self.stored_u64(&retrieved_doc, name);
+            let stored_facet = |name| self.stored_facet(&retrieved_doc, name);
+            let required = |name| {
+                stored(name).ok_or_else(|| Error::MissingStoredField {
+                    dir: self.dir.clone(),
+                    field: name.to_string(),
+                })
+            };
+
+            let url = required("url")?;
+            let title = required("title")?;
+            let heading = stored("heading").filter(|heading| !heading.is_empty());
+
+            let body = match self.return_body {
+                true => Some(required("body")?),
+                false => None,
+            };
+
+            let (snippet, snippet_highlights) = snippet_generator
+                .as_ref()
+                .map(|snippet_generator| {
+                    let snippet = snippet_generator.snippet_from_doc(&retrieved_doc);
+                    highlight::trim_start(
+                        &snippet.fragment().replace('\n', " "),
+                        snippet.highlighted(),
+                    )
+                })
+                .filter(|(snippet, _)| !snippet.trim().is_empty())
+                .map(|(snippet, highlights)| (Some(snippet.trim_end().to_string()), highlights))
+                .unwrap_or_default();
+
+            results.push(SearchResult {
+                id: required("id")?,
+                url,
+                source: stored_facet("source").concat(),
+                book: stored("book").unwrap_or_default(),
+                title,
+                chapter_path: stored_facet("chapter"),
+                depth: stored_u64("depth").unwrap_or_default(),
+                heading,
+                lang: stored("lang"),
+                edition: stored_u64("edition").map(|edition| edition as u16),
+                word_count: stored_u64("word_count").unwrap_or_default(),
+                modified: self.stored_date(&retrieved_doc, "modified"),
+                snippet,
+                snippet_lines: None,
+                body,
+                highlights: Highlights {
+                    snippet: snippet_highlights,
+                    ..Default::default()
+                },
+                code_blocks: vec![],
+                score,
+                doc_address: Some(doc_address),
+            });
         }
 
         Ok(results)
     }
+
+    /// Number of documents matching the query.
+    pub fn count(&self, query: &dyn Query) -> Result<usize, Error> {
+        match &self.searcher {
+            Some(searcher) => Ok(searcher.search(query, &Count)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Number of documents matching the query in every source, by its name.
+    pub fn count_by_source(&self, query: &dyn Query) -> Result<BTreeMap<String, usize>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut facet_collector = FacetCollector::for_field("source");
+        facet_collector.add_facet(Facet::root());
+        let facet_counts = searcher.search(query, &facet_collector)?;
+
+        Ok(facet_counts
+            .get("/")
+            .filter_map(|(facet, count)| {
+                let name = facet.to_path().first()?.to_string();
+                Some((name, count as usize))
+            })
+            .collect())
+    }
+
+    /// Urls of the chapters of the documents, read from the fast field without loading
+    /// the documents.
+    pub fn chapter_urls(&self, docs: &[DocAddress]) -> Result<Vec<String>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(vec![]);
+        };
+
+        let mut columns = HashMap::new();
+        let mut urls = Vec::with_capacity(docs.len());
+
+        for doc_address in docs {
+            let column = match columns.entry(doc_address.segment_ord) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let column = searcher
+                        .segment_reader(doc_address.segment_ord)
+                        .fast_fields()
+                        .str("chapter_url")?
+                        .ok_or_else(|| Error::SchemaMismatch(self.dir.clone()))?;
+                    entry.insert(column)
+                }
+            };
+
+            let mut url = String::new();
+            if let Some(ord) = column.term_ords(doc_address.doc_id).next() {
+                column.ord_to_str(ord, &mut url)?;
+            }
+            urls.push(url);
+        }
+
+        Ok(urls)
+    }
+
+    /// Distinct titles and headings of the chapters, with the urls of the chapters, read
+    /// from the fast fields without loading the documents.
+    pub fn chapter_titles(&self) -> Result<HashSet<(String, String)>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(HashSet::new());
+        };
+
+        let mut titles = HashSet::new();
+
+        for segment_reader in searcher.segment_readers() {
+            let column = |name| {
+                segment_reader
+                    .fast_fields()
+                    .str(name)?
+                    .ok_or_else(|| Error::SchemaMismatch(self.dir.clone()))
+            };
+            let chapter_urls = column("chapter_url")?;
+
+            for title_column in [column("title")?, column("heading")?] {
+                // Ordinals of the chapter urls and the titles, resolved once
+                let ords: HashSet<(u64, u64)> = segment_reader
+                    .doc_ids_alive()
+                    .filter_map(|doc_id| {
+                        let url_ord = chapter_urls.term_ords(doc_id).next()?;
+                        let title_ord = title_column.term_ords(doc_id).next()?;
+                        Some((url_ord, title_ord))
+                    })
+                    .collect();
+
+                for (url_ord, title_ord) in ords {
+                    let mut url = String::new();
+                    chapter_urls.ord_to_str(url_ord, &mut url)?;
+                    let mut title = String::new();
+                    title_column.ord_to_str(title_ord, &mut title)?;
+                    titles.insert((url, title));
+                }
+            }
+        }
+
+        Ok(titles)
+    }
+
+    /// Titles of the books in the index, by the name of their source.
+    pub fn book_titles(&self) -> Result<BTreeMap<String, String>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut titles = BTreeMap::new();
+
+        for name in self.count_by_source(&AllQuery)?.into_keys() {
+            let query = TermQuery::new(
+                Term::from_facet(self.field("source")?, &Facet::from_path([&name])),
+                IndexRecordOption::Basic,
+            );
+
+            if let Some((_, doc_address)) =
+                searcher.search(&query, &TopDocs::with_limit(1))?.first()
+            {
+                let doc = searcher.doc::<TantivyDocument>(*doc_address)?;
+                titles.insert(name, self.stored_str(&doc, "book").unwrap_or_default());
+            }
+        }
+
+        Ok(titles)
+    }
+
+    /// Words of the titles, headings and bodies with the number of documents they are
+    /// in, summed over the fields. Stemmed fields are left out, their terms aren't words.
+    pub fn word_frequencies(&self) -> Result<HashMap<String, u64>, Error> {
+        let Some(searcher) = &self.searcher else {
+            return Ok(HashMap::new());
+        };
+
+        let mut fields = Vec::new();
+        for name in ["title", "heading", "body"] {
+            let field = self.field(name)?;
+            if let Some(field) = self
+                .exact(name)
+                .or((!self.is_stemmed(field)).then_some(field))
+            {
+                fields.push(field);
+            }
+        }
+
+        let mut frequencies = HashMap::new();
+
+        for segment_reader in searcher.segment_readers() {
+            for &field in &fields {
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut terms = inverted_index.terms().stream()?;
+                while terms.advance() {
+                    let Ok(word) = std::str::from_utf8(terms.key()) else {
+                        continue;
+                    };
+                    *frequencies.entry(word.to_string()).or_default() +=
+                        u64::from(terms.value().doc_freq);
+                }
+            }
+        }
+
+        Ok(frequencies)
+    }
+}
+
+/// Url of the chapter of a document, without the anchor of its section.
+pub fn chapter_url(url: &str) -> &str {
+    url.split_once('#').map_or(url, |(chapter, _)| chapter)
+}
+
+/// Byte offset of the error in the query: the unknown field, the unbalanced quote or
+/// parenthesis, a dangling operator or the first special character.
+fn syntax_error_position(query: &str, err: &QueryParserError) -> Option<usize> {
+    match err {
+        QueryParserError::FieldDoesNotExist(field)
+        | QueryParserError::FieldNotIndexed(field)
+        | QueryParserError::FieldDoesNotHavePositionsIndexed(field) => {
+            query.find(&format!("{}:", field))
+        }
+        _ => {
+            let mut quote = None;
+            let mut parentheses = vec![];
+
+            for (position, c) in query.char_indices() {
+                match c {
+                    '"' if quote.is_some() => quote = None,
+                    '"' => quote = Some(position),
+                    _ if quote.is_some() => {}
+                    '(' => parentheses.push(position),
+                    ')' if parentheses.pop().is_none() => return Some(position),
+                    _ => {}
+                }
+            }
+
+            if let Some(position) = quote.or(parentheses.first().copied()) {
+                return Some(position);
+            }
+
+            let trimmed = query.trim_end();
+            if let Some(operator) = ["AND", "OR"]
+                .iter()
+                .find(|operator| trimmed.ends_with(&format!(" {}", operator)))
+            {
+                return Some(trimmed.len() - operator.len());
+            }
+
+            query.find([':', '^', '[', ']', '{', '}', '\''])
+        }
+    }
+}
+
+/// Edit distance tolerated in a token, none in short ones where a typo is another word.
+pub(crate) fn fuzzy_distance(token: &str) -> u8 {
+    match token.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{create_index, page};
+
+    #[test]
+    fn test_stemmed_and_exact_matches() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = create_index(
+            dir.path(),
+            IndexKind::Page,
+            vec![
+                page("a", "Values are borrowed"),
+                page("b", "Borrowing values of the structs in functions"),
+            ],
+        );
+        assert_eq!(index.analyzer(), Some(&AnalyzerConfig::default()));
+
+        let page = index.search("borrowing", 0, 10).unwrap();
+        let urls: Vec<_> = page.results.iter().map(|r| r.url.as_str()).collect();
+        assert_eq!(urls, ["b", "a"]);
+    }
+
+    #[test]
+    fn test_fuzzy_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = create_index(
+            dir.path(),
+            IndexKind::Page,
+            vec![
+                page("a", "A RefCell checks the borrow rules at runtime"),
+                page("b", "Borow misspelled, and a RefCel too"),
+                page("c", "Cells without borrowing"),
+                page("d", "The box and the bix"),
+            ],
+        );
+
+        let urls = |q: &str| {
+            let query = index.fuzzy_query_body(q).unwrap();
+            let page = index.search_query(query.as_ref(), 0, 10).unwrap();
+            page.results.into_iter().map(|r| r.url).collect::<Vec<_>>()
+        };
+
+        // Every token is matched, exact matches first
+        assert_eq!(urls("refcel borow"), ["b", "a"]);
+        assert_eq!(urls("refcell borrow"), ["a", "b"]);
+        // No typos in short tokens
+        assert_eq!(urls("box"), ["d"]);
+        assert_eq!(fuzzy_distance("rc"), 0);
+        assert_eq!(fuzzy_distance("borow"), 1);
+        assert_eq!(fuzzy_distance("mutability"), 2);
+    }
+
+    #[test]
+    fn test_document_fields() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = create_index(
+            dir.path(),
+            IndexKind::Code,
+            vec![IndexDocument {
+                id: "ch01.html@0".to_string(),
+                url: "ch01.html".to_string(),
+                source: "book".to_string(),
+                book: "The Rust Programming Language".to_string(),
+                title: "Installation".to_string(),
+                chapter_path: vec!["Getting Started".to_string(), "Installation".to_string()],
+                heading: "Installing rustup on Linux".to_string(),
+                body: "let x = 5;".to_string(),
+                lang: Some("rust".to_string()),
+                edition: Some(2021),
+                modified: Some(1_700_000_000),
+                ..Default::default()
+            }],
+        );
+        assert!(index.has_schema(IndexKind::Code, &AnalyzerConfig::default()));
+        assert!(!index.has_schema(IndexKind::Page, &AnalyzerConfig::default()));
+
+        let page = index.search("programming", 0, 10).unwrap();
+        let result = &page.results[0];
+
+        assert_eq!(result.id, "ch01.html@0");
+        assert_eq!(result.source, "book");
+        assert_eq!(result.book, "The Rust Programming Language");
+        assert_eq!(result.title, "Installation");
+        assert_eq!(result.chapter_path, ["Getting Started", "Installation"]);
+        assert_eq!(result.depth, 2);
+        assert_eq!(
+            result.heading.as_deref(),
+            Some("Installing rustup on Linux")
+        );
+        assert_eq!(result.lang.as_deref(), Some("rust"));
+        assert_eq!(result.edition, Some(2021));
+        assert_eq!(result.word_count, 4);
+        assert_eq!(result.modified, Some(1_700_000_000));
+    }
+
+    #[test]
+    fn test_query_syntax_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = create_index(dir.path(), IndexKind::Page, vec![]);
+
+        for (query, expected) in [
+            ("borrow \"mutable reference", Some(7)),
+            ("(lifetimes", Some(0)),
+            ("lifetimes)", Some(9)),
+            ("crate:tokio", Some(0)),
+            ("traits AND", Some(7)),
+            ("x::y", Some(1)),
+        ] {
+            match index.search(query, 0, 10) {
+                Err(Error::QuerySyntax { position, .. }) => assert_eq!(position, expected),
+                _ => panic!("`{}` should be invalid", query),
+            }
+        }
+
+        assert!(index.search("Vec<T>", 0, 10).is_ok());
+        assert!(matches!(
+            SearchIndex::open(&dir.path().join("missing").to_string_lossy()),
+            Err(Error::MissingIndex(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut index = create_index(dir.path(), IndexKind::Page, vec![page("a", "borrow")]);
+
+        assert!(matches!(
+            index.add_document(page("b", "borrow")),
+            Err(Error::ReadOnly(_))
+        ));
+        assert!(matches!(
+            index.delete_document("a"),
+            Err(Error::ReadOnly(_))
+        ));
+        assert!(matches!(index.commit(), Err(Error::ReadOnly(_))));
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut schema_builder = Schema::builder();
+        schema_builder.add_text_field("id", STRING | STORED);
+        schema_builder.add_text_field("title", TEXT | STORED);
+        Index::create_in_dir(dir.path(), schema_builder.build()).unwrap();
+
+        let mut index = SearchIndex::open_any_version(&dir.path().to_string_lossy()).unwrap();
+        index.index_writer = Some(index.index.writer(15_000_000).unwrap());
+
+        let query = UserQuery::parse("borrow book:nomicon");
+        assert!(matches!(
+            index.filter_clauses(&query),
+            Err(Error::SchemaMismatch(_))
+        ));
+        assert!(matches!(
+            index.fuzzy_query_body("borrow"),
+            Err(Error::SchemaMismatch(_))
+        ));
+        assert!(matches!(
+            index.add_document(page("a", "borrow")),
+            Err(Error::SchemaMismatch(_))
+        ));
+    }
 }
@@ -0,0 +1,772 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::error::Error;
+use crate::index::{chapter_url, IndexDocument, IndexKind, SearchIndex};
+use crate::manifest::{Manifest, SourceManifest, MANIFEST_FILE};
+use crate::parsers::RUST;
+use crate::sources::{DocumentSource, SourceError};
+use crate::tokenizer::AnalyzerConfig;
+use crate::{CODE_INDEX, PAGE_INDEX};
+
+/// Indexes documents of sources into the page and code indexes.
+///
+/// Documents are identified by their url, code blocks by the url and their ordinal
+/// in the document. Re-indexing a source adds only the new and changed documents
+/// and deletes the ones which are gone, as recorded in the manifest.
+pub struct Indexer {
+    index_dir: PathBuf,
+    index_page: SearchIndex,
+    index_code: SearchIndex,
+    manifest: Manifest,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexStats {
+    pub documents: usize,
+    pub code_blocks: usize,
+    /// Pages and code blocks added to the indexes
+    pub added: usize,
+    /// Pages and code blocks whose content changed
+    pub updated: usize,
+    /// Pages and code blocks which are no longer in the source
+    pub removed: usize,
+    /// Pages and code blocks which didn't change, or whose files couldn't be read
+    pub unchanged: usize,
+    /// Files which couldn't be read
+    pub errors: Vec<SourceError>,
+}
+
+impl Indexer {
+    /// Creates empty indexes in the directory, replacing the existing ones.
+    pub fn create(index_dir: &Path, analyzer: &AnalyzerConfig) -> Result<Self, Error> {
+        let page_dir = index_dir.join(PAGE_INDEX);
+        let code_dir = index_dir.join(CODE_INDEX);
+
+        for dir in [&page_dir, &code_dir] {
+            if dir.exists() {
+                fs::remove_dir_all(dir)?;
+            }
+            fs::create_dir_all(dir)?;
+        }
+
+        let manifest_path = index_dir.join(MANIFEST_FILE);
+        if manifest_path.exists() {
+            fs::remove_file(manifest_path)?;
+        }
+
+        Ok(Self {
+            index_dir: index_dir.to_path_buf(),
+            index_page: SearchIndex::create(
+                &page_dir.to_string_lossy(),
+                IndexKind::Page,
+                analyzer,
+            )?,
+            index_code: SearchIndex::create(
+                &code_dir.to_string_lossy(),
+                IndexKind::Code,
+                analyzer,
+            )?,
+            manifest: Manifest::default(),
+        })
+    }
+
+    /// Opens the indexes in the directory for re-indexing. They are created when
+    /// there are no indexes with a manifest yet, and rebuilt when they were built with
+    /// another analyzer or schema.
+    pub fn open(index_dir: &Path, analyzer: &AnalyzerConfig) -> Result<Self, Error> {
+        let page_dir = index_dir.join(PAGE_INDEX);
+        let code_dir = index_dir.join(CODE_INDEX);
+
+        let exists = [&page_dir, &code_dir]
+            .iter()
+            .all(|dir| dir.join("meta.json").exists())
+            && index_dir.join(MANIFEST_FILE).exists();
+
+        if !exists {
+            return Self::create(index_dir, analyzer);
+        }
+
+        let open = || -> Result<Self, Error> {
+            Ok(Self {
+                index_dir: index_dir.to_path_buf(),
+                index_page: SearchIndex::open_for_update(&page_dir.to_string_lossy())?,
+                index_code: SearchIndex::open_for_update(&code_dir.to_string_lossy())?,
+                manifest: Manifest::load(index_dir)?,
+            })
+        };
+
+        let indexer = match open() {
+            Ok(indexer) => indexer,
+            Err(Error::SchemaVersion { .. } | Error::SchemaMismatch(_)) => {
+                println!("The schema changed, rebuilding the indexes");
+                return Self::create(index_dir, analyzer);
+            }
+            Err(err) => return Err(err),
+        };
+
+        if indexer.index_page.analyzer() != Some(analyzer) {
+            println!("The analyzer changed, rebuilding the indexes");
+            drop(indexer);
+            return Self::create(index_dir, analyzer);
+        }
+
+        if !indexer.index_page.has_schema(IndexKind::Page, analyzer)
+            || !indexer.index_code.has_schema(IndexKind::Code, analyzer)
+        {
+            println!("The schema changed, rebuilding the indexes");
+            drop(indexer);
+            return Self::create(index_dir, analyzer);
+        }
+
+        Ok(indexer)
+    }
+
+    /// Builds indexes of the current schema in `index_dir` from the documents stored
+    /// in the indexes of `from`, without reading the sources again. The fields older
+    /// schemas don't have are derived from the stored ones where possible, ie. the
+    /// book from the ` - {book}` suffix of the titles.
+    ///
+    /// Indexes without ids and a manifest, like the first ones, are identified by their
+    /// urls and the ordinals of their code blocks, and their sources are the ones of
+    /// `sources` by the titles of their books.
+    pub fn migrate(
+        from: &Path,
+        index_dir: &Path,
+        analyzer: &AnalyzerConfig,
+        sources: &BTreeMap<String, String>,
+    ) -> Result<(Self, IndexStats), Error> {
+        let previous = Manifest::load(from)?;
+        let mut indexer = Self::create(index_dir, analyzer)?;
+        let mut stats = IndexStats::default();
+
+        for (dir, kind) in [(PAGE_INDEX, IndexKind::Page), (CODE_INDEX, IndexKind::Code)] {
+            let index = SearchIndex::open_any_version(&from.join(dir).to_string_lossy())?;
+            // Numbers of the code blocks of the urls so far
+            let mut ordinals = HashMap::new();
+
+            for mut document in index.stored_documents()? {
+                upgrade_document(&mut document, kind, &mut ordinals);
+
+                let name = source_name(&previous, &document, kind, sources);
+                document.source = name.clone();
+
+                let current = indexer
+                    .manifest
+                    .sources
+                    .entry(name)
+                    .or_insert_with_key(|name| match previous.sources.get(name) {
+                        Some(source) => SourceManifest {
+                            indexed_at: source.indexed_at,
+                            ..Default::default()
+                        },
+                        None => SourceManifest::new(),
+                    });
+                let (index, current) = match kind {
+                    IndexKind::Page => (&mut indexer.index_page, &mut current.pages),
+                    IndexKind::Code => (&mut indexer.index_code, &mut current.code_blocks),
+                };
+                // Hashed again, the hashes of older versions differ or are missing
+                let empty = BTreeMap::new();
+                update(index, document, &empty, current, &mut stats)?;
+
+                match kind {
+                    IndexKind::Page => stats.documents += 1,
+                    IndexKind::Code => stats.code_blocks += 1,
+                }
+            }
+        }
+
+        Ok((indexer, stats))
+    }
+
+    /// Names of the sources in the indexes.
+    pub fn source_names(&self) -> Vec<String> {
+        self.manifest.sources.keys().cloned().collect()
+    }
+
+    /// Indexes the new and changed documents of the source and its code blocks, and
+    /// deletes the ones which are gone. Files which can't be read are reported and
+    /// collected in the stats, their previous documents are kept, all of them when the
+    /// source itself can't be read.
+    pub fn index(&mut self, name: &str, source: &dyn DocumentSource) -> Result<IndexStats, Error> {
+        let mut stats = IndexStats::default();
+
+        let previous = self.manifest.sources.remove(name).unwrap_or_default();
+        let mut current = SourceManifest::new();
+
+        for document in source.documents() {
+            let document = match document {
+                Ok(document) => document,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    stats.errors.push(err);
+                    continue;
+                }
+            };
+
+            let modified = document
+                .metadata
+                .modified
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs() as i64);
+
+            // Fields shared by the page and its code blocks
+            let section = IndexDocument {
+                url: document.url.clone(),
+                source: name.to_string(),
+                book: source.title().to_string(),
+                title: document.title,
+                chapter_path: document.metadata.chapter_path,
+                heading: document.metadata.heading,
+                modified,
+                ..Default::default()
+            };
+
+            let page = IndexDocument {
+                id: document.url.clone(),
+                body: document.body,
+                ..section.clone()
+            };
+            update(
+                &mut self.index_page,
+                page,
+                &previous.pages,
+                &mut current.pages,
+                &mut stats,
+            )?;
+            stats.documents += 1;
+
+            // Index code blocks found in the document
+            for (ordinal, code_block) in document.code_blocks.into_iter().enumerate() {
+                let code = IndexDocument {
+                    id: format!("{}@{}", document.url, ordinal),
+                    body: code_block.code,
+                    lang: Some(code_block.lang),
+                    edition: code_block.edition,
+                    ..section.clone()
+                };
+                update(
+                    &mut self.index_code,
+                    code,
+                    &previous.code_blocks,
+                    &mut current.code_blocks,
+                    &mut stats,
+                )?;
+                stats.code_blocks += 1;
+            }
+        }
+
+        // The previous documents of the files which couldn't be read are kept
+        stats.unchanged += keep_unreadable(&previous.pages, &mut current.pages, &stats.errors);
+        stats.unchanged += keep_unreadable(
+            &previous.code_blocks,
+            &mut current.code_blocks,
+            &stats.errors,
+        );
+
+        stats.removed += remove(&mut self.index_page, &previous.pages, &current.pages)?;
+        stats.removed += remove(
+            &mut self.index_code,
+            &previous.code_blocks,
+            &current.code_blocks,
+        )?;
+
+        self.manifest.sources.insert(name.to_string(), current);
+
+        Ok(stats)
+    }
+
+    /// Deletes all documents of the source, ie. when it was removed from the config.
+    pub fn remove_source(&mut self, name: &str) -> Result<IndexStats, Error> {
+        let mut stats = IndexStats::default();
+
+        if let Some(previous) = self.manifest.sources.remove(name) {
+            let empty = BTreeMap::new();
+            stats.removed += remove(&mut self.index_page, &previous.pages, &empty)?;
+            stats.removed += remove(&mut self.index_code, &previous.code_blocks, &empty)?;
+        }
+
+        Ok(stats)
+    }
+
+    /// Commits the indexes, then saves the manifest.
+    pub fn commit(&mut self) -> Result<(), Error> {
+        self.index_page.commit()?;
+        self.index_code.commit()?;
+        self.manifest.save(&self.index_dir)?;
+
+        Ok(())
+    }
+}
+
+/// Adds the document unless it's unchanged since the previous indexing, replacing
+/// its previous version.
+fn update(
+    index: &mut SearchIndex,
+    mut document: IndexDocument,
+    previous: &BTreeMap<String, String>,
+    current: &mut BTreeMap<String, String>,
+    stats: &mut IndexStats,
+) -> Result<(), Error> {
+    // A chapter listed twice in the summary is indexed once
+    if current.contains_key(&document.id) {
+        return Ok(());
+    }
+
+    document.hash = content_hash(&document);
+    current.insert(document.id.clone(), document.hash.clone());
+
+    match previous.get(&document.id) {
+        Some(hash) if *hash == document.hash => stats.unchanged += 1,
+        Some(_) => {
+            index.delete_document(&document.id)?;
+            index.add_document(document)?;
+            stats.updated += 1;
+        }
+        None => {
+            index.add_document(document)?;
+            stats.added += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fills the fields which documents of older schemas don't have.
+fn upgrade_document(
+    document: &mut IndexDocument,
+    kind: IndexKind,
+    ordinals: &mut HashMap<String, usize>,
+) {
+    // Documents had no ids before the incremental indexing, code blocks are numbered
+    // in the order they are stored
+    if document.id.is_empty() {
+        document.id = match kind {
+            IndexKind::Page => document.url.clone(),
+            IndexKind::Code => {
+                let ordinal = ordinals.entry(document.url.clone()).or_default();
+                *ordinal += 1;
+                format!("{}@{}", document.url, *ordinal - 1)
+            }
+        };
+    }
+
+    // Titles were `{chapter} - {book}` before the book had its own field
+    if document.book.is_empty() {
+        if let Some((title, book)) = document.title.rsplit_once(" - ") {
+            document.book = book.to_string();
+            document.title = title.to_string();
+        }
+    }
+
+    if document.chapter_path.is_empty() {
+        document.chapter_path = vec![document.title.clone()];
+    }
+
+    // Only Rust code blocks were indexed before their language was stored
+    if kind == IndexKind::Code && document.lang.is_none() {
+        document.lang = Some(RUST.to_string());
+    }
+}
+
+/// Name of the source of a migrated document: the one listing it in the manifest, the
+/// stored one, or the one of its book in `sources`. Other books are named after their
+/// titles.
+fn source_name(
+    previous: &Manifest,
+    document: &IndexDocument,
+    kind: IndexKind,
+    sources: &BTreeMap<String, String>,
+) -> String {
+    let listed = previous.sources.iter().find_map(|(name, source)| {
+        let ids = match kind {
+            IndexKind::Page => &source.pages,
+            IndexKind::Code => &source.code_blocks,
+        };
+        ids.contains_key(&document.id).then_some(name)
+    });
+
+    if let Some(name) = listed.or(sources.get(&document.book)) {
+        return name.clone();
+    }
+    if !document.source.is_empty() {
+        return document.source.clone();
+    }
+
+    document
+        .book
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Copies the previous documents of the chapters which couldn't be read into the current
+/// ones, all the missing ones when the source itself couldn't be read. Returns their count.
+fn keep_unreadable(
+    previous: &BTreeMap<String, String>,
+    current: &mut BTreeMap<String, String>,
+    errors: &[SourceError],
+) -> usize {
+    let whole_source = errors.iter().any(|err| err.url.is_none());
+    let unreadable: Vec<&str> = errors
+        .iter()
+        .filter_map(|err| err.url.as_deref())
+        .map(chapter_url)
+        .collect();
+
+    let mut kept = 0;
+
+    for (id, hash) in previous {
+        // Code blocks are identified by the url of their document and their ordinal
+        let url = id.rsplit_once('@').map_or(id.as_str(), |(url, _)| url);
+        if current.contains_key(id) || !(whole_source || unreadable.contains(&chapter_url(url))) {
+            continue;
+        }
+
+        current.insert(id.clone(), hash.clone());
+        kept += 1;
+    }
+
+    kept
+}
+
+/// Deletes the previous documents missing from the current ones, returns their count.
+fn remove(
+    index: &mut SearchIndex,
+    previous: &BTreeMap<String, String>,
+    current: &BTreeMap<String, String>,
+) -> Result<usize, Error> {
+    let mut removed = 0;
+
+    for id in previous.keys().filter(|id| !current.contains_key(*id)) {
+        index.delete_document(id)?;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
+
+/// Hash of the indexed fields of the document. The modification time isn't hashed, a
+/// touched file with the same content isn't re-indexed.
+fn content_hash(document: &IndexDocument) -> String {
+    let mut hasher = blake3::Hasher::new();
+
+    let chapter_path = document.chapter_path.join("\0");
+    let lang = document.lang.clone().unwrap_or_default();
+    let edition = document
+        .edition
+        .map(|edition| edition.to_string())
+        .unwrap_or_default();
+
+    for field in [
+        &document.url,
+        &document.source,
+        &document.book,
+        &document.title,
+        &chapter_path,
+        &document.heading,
+        &document.body,
+        &lang,
+        &edition,
+    ] {
+        hasher.update(field.as_bytes());
+        hasher.update(b"\0");
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::SourceDocument;
+    use crate::test_utils::{self, rust, TestSource};
+
+    /// Document titled by its url.
+    fn document(url: &str, body: &str, code: &[&str]) -> SourceDocument {
+        test_utils::document(url, url, body, rust(code))
+    }
+
+    #[test]
+    fn test_reindex_only_changed_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let source = TestSource {
+            title: "Test",
+            documents: vec![
+                document("a", "first", &["fn a() {}"]),
+                document("b", "second", &["fn b() {}", "fn c() {}"]),
+            ],
+            ..Default::default()
+        };
+
+        let mut indexer = Indexer::open(dir, &AnalyzerConfig::default()).unwrap();
+        let stats = indexer.index("test", &source).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!((stats.added, stats.updated, stats.removed), (5, 0, 0));
+        drop(indexer);
+
+        let source = TestSource {
+            title: "Test",
+            documents: vec![
+                document("b", "second, changed", &["fn b() {}"]),
+                document("c", "third", &[]),
+            ],
+            ..Default::default()
+        };
+
+        let mut indexer = Indexer::open(dir, &AnalyzerConfig::default()).unwrap();
+        let stats = indexer.index("test", &source).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!(
+            (stats.added, stats.updated, stats.removed, stats.unchanged),
+            (1, 1, 3, 1)
+        );
+        assert_eq!(indexer.index_page.num_docs(), 2);
+        assert_eq!(indexer.index_code.num_docs(), 1);
+        drop(indexer);
+
+        let mut indexer = Indexer::open(dir, &AnalyzerConfig::default()).unwrap();
+        let stats = indexer.remove_source("test").unwrap();
+        indexer.commit().unwrap();
+        assert_eq!(stats.removed, 3);
+        assert_eq!(indexer.index_page.num_docs(), 0);
+        assert!(indexer.source_names().is_empty());
+    }
+
+    #[test]
+    fn test_keep_unreadable_documents() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        let source = TestSource {
+            title: "Test",
+            documents: vec![
+                document("a", "first", &["fn a() {}"]),
+                document("b#intro", "second", &["fn b() {}"]),
+                document("b#usage", "third", &[]),
+            ],
+            ..Default::default()
+        };
+        let mut indexer = Indexer::open(dir, &AnalyzerConfig::default()).unwrap();
+        indexer.index("test", &source).unwrap();
+
+        // The file of the chapter b can't be read, a is gone
+        let source = TestSource {
+            title: "Test",
+            unreadable: vec![Some("b")],
+            ..Default::default()
+        };
+        let stats = indexer.index("test", &source).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!((stats.removed, stats.unchanged), (2, 3));
+        assert_eq!(stats.errors.len(), 1);
+        assert_eq!(indexer.index_page.num_docs(), 2);
+        assert_eq!(indexer.index_code.num_docs(), 1);
+
+        // The source can't be read at all
+        let source = TestSource {
+            title: "Test",
+            unreadable: vec![None],
+            ..Default::default()
+        };
+        let stats = indexer.index("test", &source).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!((stats.removed, stats.unchanged), (0, 3));
+        assert_eq!(indexer.index_page.num_docs(), 2);
+
+        let manifest = Manifest::load(dir).unwrap();
+        let pages: Vec<&str> = manifest.sources["test"]
+            .pages
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(pages, ["b#intro", "b#usage"]);
+    }
+
+    /// Builds an index with the schema from before the schema was versioned.
+    fn create_legacy_index(dir: &Path, documents: &[(&str, &str, &str)]) {
+        use tantivy::schema::{Schema, STORED, STRING, TEXT};
+        use tantivy::{doc, Index};
+
+        let mut schema_builder = Schema::builder();
+        let id = schema_builder.add_text_field("id", STRING | STORED);
+        let hash = schema_builder.add_text_field("hash", STRING | STORED);
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let url = schema_builder.add_text_field("url", TEXT | STORED);
+        let heading = schema_builder.add_text_field("heading", TEXT | STORED);
+        let body = schema_builder.add_text_field("body", TEXT | STORED);
+
+        fs::create_dir_all(dir).unwrap();
+        let index = Index::create_in_dir(dir, schema_builder.build()).unwrap();
+        let mut index_writer = index
+            .writer::<tantivy::TantivyDocument>(15_000_000)
+            .unwrap();
+
+        for (doc_id, doc_title, doc_body) in documents {
+            index_writer
+                .add_document(doc!(
+                    id => *doc_id,
+                    hash => "legacy",
+                    title => *doc_title,
+                    url => "https://example.com/intro.html",
+                    heading => "",
+                    body => *doc_body,
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_legacy_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path();
+        let to = from.join("migrated");
+
+        create_legacy_index(
+            &from.join(PAGE_INDEX),
+            &[("intro", "Introduction - Test Book", "Hello")],
+        );
+        create_legacy_index(
+            &from.join(CODE_INDEX),
+            &[("intro@0", "Introduction - Test Book", "fn main() {}")],
+        );
+
+        let mut previous = SourceManifest::default();
+        previous
+            .pages
+            .insert("intro".to_string(), "legacy".to_string());
+        previous
+            .code_blocks
+            .insert("intro@0".to_string(), "legacy".to_string());
+        Manifest {
+            sources: [("test".to_string(), previous)].into(),
+        }
+        .save(from)
+        .unwrap();
+
+        assert!(matches!(
+            SearchIndex::open(&from.join(PAGE_INDEX).to_string_lossy()),
+            Err(Error::SchemaVersion { found: None, .. })
+        ));
+
+        let (mut indexer, stats) =
+            Indexer::migrate(from, &to, &AnalyzerConfig::default(), &BTreeMap::new()).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!((stats.documents, stats.code_blocks), (1, 1));
+        drop(indexer);
+
+        let index_page = SearchIndex::open(&to.join(PAGE_INDEX).to_string_lossy()).unwrap();
+        let pages = index_page.stored_documents().unwrap();
+        assert_eq!(pages[0].source, "test");
+        assert_eq!(pages[0].book, "Test Book");
+        assert_eq!(pages[0].title, "Introduction");
+        assert_eq!(pages[0].chapter_path, ["Introduction"]);
+
+        let index_code = SearchIndex::open(&to.join(CODE_INDEX).to_string_lossy()).unwrap();
+        let code_blocks = index_code.stored_documents().unwrap();
+        assert_eq!(code_blocks[0].lang.as_deref(), Some(RUST));
+
+        let manifest = Manifest::load(&to).unwrap();
+        assert_eq!(manifest.sources["test"].pages["intro"], pages[0].hash);
+        assert_eq!(
+            manifest.sources["test"].code_blocks["intro@0"],
+            code_blocks[0].hash
+        );
+    }
+
+    /// Builds an index with the first schema: no ids, hashes or manifest.
+    fn create_baseline_index(dir: &Path, documents: &[(&str, &str, &str)]) {
+        use tantivy::schema::{Schema, STORED, TEXT};
+        use tantivy::{doc, Index};
+
+        let mut schema_builder = Schema::builder();
+        let title = schema_builder.add_text_field("title", TEXT | STORED);
+        let url = schema_builder.add_text_field("url", TEXT | STORED);
+        let body = schema_builder.add_text_field("body", TEXT | STORED);
+
+        fs::create_dir_all(dir).unwrap();
+        let index = Index::create_in_dir(dir, schema_builder.build()).unwrap();
+        let mut index_writer = index
+            .writer::<tantivy::TantivyDocument>(15_000_000)
+            .unwrap();
+
+        for (doc_title, doc_url, doc_body) in documents {
+            index_writer
+                .add_document(doc!(
+                    title => *doc_title,
+                    url => *doc_url,
+                    body => *doc_body,
+                ))
+                .unwrap();
+        }
+        index_writer.commit().unwrap();
+    }
+
+    #[test]
+    fn test_migrate_baseline_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path();
+        let to = from.join("migrated");
+        let url = "https://example.com/intro.html";
+
+        create_baseline_index(
+            &from.join(PAGE_INDEX),
+            &[
+                ("Introduction - Test Book", url, "Hello"),
+                (
+                    "Appendix - Other Book",
+                    "https://example.com/other.html",
+                    "Bye",
+                ),
+            ],
+        );
+        create_baseline_index(
+            &from.join(CODE_INDEX),
+            &[
+                ("Introduction - Test Book", url, "fn main() {}"),
+                ("Introduction - Test Book", url, "fn test() {}"),
+            ],
+        );
+
+        let sources = [("Test Book".to_string(), "test".to_string())].into();
+        let (mut indexer, stats) =
+            Indexer::migrate(from, &to, &AnalyzerConfig::default(), &sources).unwrap();
+        indexer.commit().unwrap();
+        assert_eq!((stats.documents, stats.code_blocks), (2, 2));
+        drop(indexer);
+
+        let index_page = SearchIndex::open(&to.join(PAGE_INDEX).to_string_lossy()).unwrap();
+        let pages = index_page.stored_documents().unwrap();
+        let page = pages.iter().find(|page| page.url == url).unwrap();
+        assert_eq!(page.id, url);
+        assert_eq!(page.source, "test");
+        assert_eq!(page.book, "Test Book");
+        assert_eq!(page.title, "Introduction");
+
+        // Books of no configured source are named after their titles
+        let other = pages.iter().find(|page| page.url != url).unwrap();
+        assert_eq!(other.source, "other-book");
+
+        let index_code = SearchIndex::open(&to.join(CODE_INDEX).to_string_lossy()).unwrap();
+        let code_blocks = index_code.stored_documents().unwrap();
+        let mut ids: Vec<_> = code_blocks.iter().map(|block| block.id.clone()).collect();
+        ids.sort();
+        assert_eq!(ids, [format!("{}@0", url), format!("{}@1", url)]);
+
+        let manifest = Manifest::load(&to).unwrap();
+        assert_eq!(manifest.sources["test"].pages[url], page.hash);
+        assert_eq!(manifest.sources["other-book"].pages.len(), 1);
+        for block in &code_blocks {
+            assert!(!block.hash.is_empty());
+            assert_eq!(manifest.sources["test"].code_blocks[&block.id], block.hash);
+        }
+    }
+}
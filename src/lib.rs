@@ -1,6 +1,24 @@
+pub mod autocomplete;
+pub mod config;
+pub mod error;
+pub mod generations;
+pub mod highlight;
 pub mod index;
+pub mod indexer;
+pub mod manifest;
 pub mod parsers;
+pub mod query;
 pub mod ranking;
+pub mod sources;
+pub mod spelling;
+pub mod synonyms;
+#[cfg(test)]
+mod test_utils;
+pub mod tokenizer;
 
-pub const INDEX_PAGE_DIR: &str = "indexes/page";
-pub const INDEX_CODE_DIR: &str = "indexes/code";
+/// Default directory with the indexes
+pub const INDEX_DIR: &str = "indexes";
+/// Subdirectory of the page index
+pub const PAGE_INDEX: &str = "page";
+/// Subdirectory of the code index
+pub const CODE_INDEX: &str = "code";
@@ -1,91 +1,457 @@
-use axum::extract::{Query, State};
-use axum::{http::StatusCode, response::IntoResponse, routing::get, Json, Router};
-use rust_indexed::index::SearchResult;
-use rust_indexed::ranking::{Ranking, SearchFlags};
-use serde::{Deserialize, Serialize};
-use std::sync::{Arc, RwLock};
-use std::thread::sleep;
-use std::time::Duration;
-use std::time::Instant;
-use tokio::task;
-
-struct AppState {
-    page_index: RwLock<Ranking>,
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use rust_indexed::config::Config;
+use rust_indexed::generations::Generations;
+use rust_indexed::index::{SearchIndex, SearchResult};
+use rust_indexed::indexer::{IndexStats, Indexer};
+use rust_indexed::query::BookFilter;
+use rust_indexed::ranking::{Ranking, SearchFlags, DEFAULT_LIMIT};
+use rust_indexed::synonyms::{Synonyms, SYNONYMS_FILE};
+use rust_indexed::{CODE_INDEX, INDEX_DIR, PAGE_INDEX};
+
+mod server;
+
+/// Search in Rust books and tutorials
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Config with the indexed sources
+    #[arg(long, global = true, default_value = "config.toml")]
+    config: PathBuf,
+
+    /// Directory with the indexes
+    #[arg(long, global = true, default_value = INDEX_DIR)]
+    index_dir: PathBuf,
+
+    /// Synonyms to rewrite the queries with
+    #[arg(long, global = true, default_value = SYNONYMS_FILE)]
+    synonyms: PathBuf,
+
+    /// Serves the API when no command is given
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Index the new and changed documents of the sources from the config
+    Index {
+        /// Index only the source with this name, can be repeated
+        #[arg(long = "source", value_name = "NAME")]
+        sources: Vec<String>,
+
+        /// Rebuild the indexes from scratch, of all the sources, the ones which aren't
+        /// indexed would be dropped
+        #[arg(long, conflicts_with = "sources")]
+        full: bool,
+    },
+    /// Serve the search API, SIGHUP reloads the indexes and synonyms
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:3000")]
+        listen: String,
+
+        /// Enables `POST /admin/reload` with this bearer token
+        #[arg(long)]
+        admin_token: Option<String>,
+    },
+    /// Search the indexes
+    Search {
+        query: Vec<String>,
+
+        /// Search only code blocks
+        #[arg(long)]
+        code: bool,
+
+        /// Search only these books, by source name, or not the ones prefixed with `-`:
+        /// `book,nomicon` or `-tokio`
+        #[arg(long, default_value = "")]
+        books: String,
+
+        /// Search the suggested correction of a query without results instead
+        #[arg(long)]
+        autocorrect: bool,
+
+        /// Print whole code blocks, not only their snippets
+        #[arg(long)]
+        full_body: bool,
+
+        /// Group the results by chapters, with their code blocks
+        #[arg(long)]
+        group: bool,
+
+        /// Page number, starting from 1
+        #[arg(long, default_value_t = 1)]
+        page: usize,
+
+        /// Print the rewrites of the query
+        #[arg(long)]
+        debug: bool,
+    },
+    /// Print statistics of the indexes
+    Stats,
+    /// Upgrade the indexes to the current schema and the analyzer from the config,
+    /// from the stored documents without reading the sources
+    Migrate,
+    /// Switch back to the previous generation of the indexes
+    Rollback,
 }
 
-#[tokio::main]
-async fn main() {
-    // initialize tracing
-    // tracing_subscriber::fmt::init();
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-    let app_state = Arc::new(AppState {
-        page_index: RwLock::new(Ranking::default()),
+    let command = cli.command.unwrap_or(Command::Serve {
+        listen: "127.0.0.1:3000".to_string(),
+        admin_token: None,
     });
 
-    let app = Router::new()
-        .route("/search/", get(search)) // API
-        .with_state(app_state);
+    let result = match command {
+        Command::Index { sources, full } => index(&cli.config, &cli.index_dir, &sources, full),
+        Command::Serve {
+            listen,
+            admin_token,
+        } => tokio::runtime::Runtime::new()
+            .and_then(|runtime| {
+                runtime.block_on(server::serve(
+                    &listen,
+                    &cli.index_dir,
+                    &cli.synonyms,
+                    admin_token,
+                ))
+            })
+            .map(|_| ExitCode::SUCCESS)
+            .map_err(|err| err.into()),
+        Command::Search {
+            query,
+            code,
+            books,
+            autocorrect,
+            full_body,
+            group,
+            page,
+            debug,
+        } => search(
+            &cli.index_dir,
+            &cli.synonyms,
+            &query.join(" "),
+            search_flags(code, autocorrect, full_body, group),
+            &BookFilter::parse(&books),
+            page,
+            debug,
+        ),
+        Command::Stats => stats(&cli.index_dir),
+        Command::Migrate => migrate(&cli.config, &cli.index_dir),
+        Command::Rollback => rollback(&cli.index_dir),
+    };
+
+    match result {
+        Ok(exit_code) => exit_code,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            ExitCode::from(2)
+        }
+    }
+}
+
+/// Indexes the sources into a new generation and prints a summary, fails if any file
+/// couldn't be read. The generation becomes current once it's validated, the files which
+/// couldn't be read keep their previously indexed documents.
+///
+/// Sources which are no longer in the config are removed from the indexes, unless
+/// only some sources are indexed.
+fn index(
+    config: &Path,
+    index_dir: &Path,
+    names: &[String],
+    full: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let config = Config::load(config)?;
+
+    if let Some(name) = names
+        .iter()
+        .find(|name| !config.sources.iter().any(|source| &source.name == *name))
+    {
+        return Err(format!("Unknown source `{}`", name).into());
+    }
+
+    let generations = Generations::new(index_dir);
+    let dir = generations.create(!full)?;
+
+    let mut indexer = Indexer::open(&dir, &config.analyzer)?;
+    let mut report = vec![];
+
+    for source in &config.sources {
+        if !names.is_empty() && !names.contains(&source.name) {
+            continue;
+        }
+        let stats = indexer.index(&source.name, source.build().as_ref())?;
+        report.push((source.name.clone(), stats));
+    }
+
+    if names.is_empty() {
+        for name in indexer.source_names() {
+            if !config.sources.iter().any(|source| source.name == name) {
+                let stats = indexer.remove_source(&name)?;
+                report.push((name, stats));
+            }
+        }
+    }
+
+    indexer.commit()?;
+    drop(indexer);
+    activate(&generations, &dir)?;
+
+    let mut total = IndexStats::default();
+
+    println!();
+    print_stats_header();
 
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
+    for (name, stats) in report {
+        print_stats(&name, &stats);
+        total.documents += stats.documents;
+        total.code_blocks += stats.code_blocks;
+        total.added += stats.added;
+        total.updated += stats.updated;
+        total.removed += stats.removed;
+        total.unchanged += stats.unchanged;
+        total.errors.extend(stats.errors);
+    }
 
-    axum::serve(listener, app).await.unwrap();
+    print_stats("total", &total);
+
+    if total.errors.is_empty() {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    eprintln!();
+    eprintln!("Failed to index {} files:", total.errors.len());
+    for err in &total.errors {
+        eprintln!("  {}", err);
+    }
+    eprintln!("Their previously indexed documents were kept");
+
+    Ok(ExitCode::FAILURE)
 }
 
-async fn search(
-    Query(params): Query<Params>,
-    State(state): State<Arc<AppState>>,
-) -> impl IntoResponse {
-    let q_debug = params.q.clone();
-    let mut search_flags = SearchFlags::DEFAULT;
-
-    let q = match params.q.starts_with("code ") {
-        false => params.q,
-        true => {
-            search_flags |= SearchFlags::CODE_ONLY;
-            params.q[5..].to_string()
+/// Validates the generation and makes it current, deletes it when it's invalid.
+fn activate(generations: &Generations, dir: &Path) -> Result<(), Box<dyn Error>> {
+    if let Err(err) = generations.validate(dir) {
+        std::fs::remove_dir_all(dir)?;
+        return Err(err.into());
+    }
+    generations.activate(dir)?;
+    println!("Activated generation {:?}", dir);
+
+    Ok(())
+}
+
+/// Rebuilds the current indexes into a new generation with the current schema.
+fn migrate(config: &Path, index_dir: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let config = Config::load(config)?;
+
+    let generations = Generations::new(index_dir);
+    let from = generations.current_dir()?;
+    let dir = generations.create(false)?;
+
+    let sources = config
+        .sources
+        .iter()
+        .map(|source| (source.title.clone(), source.name.clone()))
+        .collect();
+    let (mut indexer, stats) = match Indexer::migrate(&from, &dir, &config.analyzer, &sources) {
+        Ok(migrated) => migrated,
+        Err(err) => {
+            std::fs::remove_dir_all(&dir)?;
+            return Err(err.into());
         }
     };
+    indexer.commit()?;
+    drop(indexer);
+    activate(&generations, &dir)?;
+
+    println!();
+    print_stats_header();
+    print_stats("migrated", &stats);
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_stats_header() {
+    println!(
+        "{:<24} {:>10} {:>12} {:>8} {:>8} {:>8} {:>10} {:>8}",
+        "source", "documents", "code blocks", "added", "updated", "removed", "unchanged", "errors"
+    );
+}
 
-    let _ = params.page.unwrap_or(1);
+fn print_stats(name: &str, stats: &IndexStats) {
+    println!(
+        "{:<24} {:>10} {:>12} {:>8} {:>8} {:>8} {:>10} {:>8}",
+        name,
+        stats.documents,
+        stats.code_blocks,
+        stats.added,
+        stats.updated,
+        stats.removed,
+        stats.unchanged,
+        stats.errors.len()
+    );
+}
 
-    let start = Instant::now();
+/// Prints the code or the snippet of the result.
+fn print_text(result: &SearchResult) {
+    match (&result.body, &result.snippet, result.snippet_lines) {
+        (Some(body), _, _) => print_code(body, 1, false, false),
+        (None, Some(snippet), Some(lines)) => print_code(
+            snippet,
+            lines.start,
+            lines.elided_before,
+            lines.elided_after,
+        ),
+        (None, Some(snippet), None) => println!("  {}", snippet),
+        (None, None, _) => {}
+    }
+}
+
+/// Prints the lines of code numbered from `start`, with markers of the left out lines.
+fn print_code(code: &str, start: usize, elided_before: bool, elided_after: bool) {
+    if elided_before {
+        println!("    {:>4} ...", "");
+    }
+    for (number, line) in (start..).zip(code.lines()) {
+        println!("    {:>4} | {}", number, line);
+    }
+    if elided_after {
+        println!("    {:>4} ...", "");
+    }
+}
 
-    let results = task::spawn_blocking(move || {
-        sleep(Duration::from_millis(200));
-        state.page_index.read().unwrap().search(&q, search_flags)
-    })
-    .await
-    .unwrap();
+fn search_flags(code: bool, autocorrect: bool, full_body: bool, group: bool) -> SearchFlags {
+    let mut flags = SearchFlags::DEFAULT;
+    if code {
+        flags |= SearchFlags::CODE_ONLY;
+    }
+    if autocorrect {
+        flags |= SearchFlags::AUTOCORRECT;
+    }
+    if full_body {
+        flags |= SearchFlags::FULL_BODY;
+    }
+    if group {
+        flags |= SearchFlags::GROUP;
+    }
+    flags
+}
 
-    let duration = start.elapsed();
+fn search(
+    index_dir: &Path,
+    synonyms: &Path,
+    q: &str,
+    flags: SearchFlags,
+    books: &BookFilter,
+    page: usize,
+    debug: bool,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let mut ranking = Ranking::open(index_dir)?;
+    ranking.set_synonyms(Synonyms::load(synonyms)?);
 
+    let offset = (page.max(1) - 1) * DEFAULT_LIMIT;
+
+    let results = ranking.search(q, books, flags, offset, DEFAULT_LIMIT)?;
+
+    for part in &results.ignored {
+        eprintln!("Ignored `{}`", part);
+    }
+
+    match (&results.suggestion, results.autocorrected) {
+        (Some(suggestion), true) => println!("Showing results for: {}\n", suggestion),
+        (Some(suggestion), false) => println!("Did you mean: {}\n", suggestion),
+        (None, _) => {}
+    }
+
+    if debug {
+        for expansion in &results.expansions {
+            println!("Expanded to: {}", expansion);
+        }
+        println!();
+    }
+
+    for result in &results.results {
+        match &result.heading {
+            Some(heading) => println!("{} ({})", heading, result.title),
+            None => println!("{}", result.title),
+        }
+        println!("  {} > {}", result.book, result.chapter_path.join(" > "));
+        println!("  {}", result.url);
+        print_text(result);
+        for code_block in &result.code_blocks {
+            println!("  Code in {}", code_block.url);
+            print_text(code_block);
+        }
+        println!();
+    }
+
+    let unit = match flags.contains(SearchFlags::GROUP) {
+        true => "chapters",
+        false => "results",
+    };
     println!(
-        "{} results. duration = {:?} query = `{}`",
-        results.len(),
-        duration,
-        q_debug
+        "{}-{} of {}{} {}",
+        (offset + 1).min(results.total),
+        offset + results.results.len(),
+        results.total,
+        if results.truncated { "+" } else { "" },
+        unit
     );
 
-    (
-        StatusCode::OK,
-        Json(SearchResponse {
-            results,
-            duration_milis: duration.as_millis(),
-        }),
-    )
+    if !results.books.is_empty() {
+        let counts: Vec<_> = results
+            .books
+            .iter()
+            .map(|book| format!("{} ({})", book.title, book.count))
+            .collect();
+        println!("{}", counts.join(" · "));
+    }
+
+    Ok(ExitCode::SUCCESS)
 }
 
-// the output to our `search` handler
-#[derive(Serialize)]
-struct SearchResponse {
-    results: Vec<SearchResult>,
-    duration_milis: u128,
+fn stats(index_dir: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let generations = Generations::new(index_dir);
+
+    if let Some(generation) = generations.current()? {
+        println!("generation {}", generation);
+    }
+
+    for name in [PAGE_INDEX, CODE_INDEX] {
+        let dir = generations.current_dir()?.join(name);
+        let index = SearchIndex::open_any_version(&dir.to_string_lossy())?;
+
+        let size: u64 = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum();
+
+        println!(
+            "{:<6} {:>8} documents {:>4} segments {:>8} KiB  schema version {}",
+            name,
+            index.num_docs(),
+            index.num_segments(),
+            size / 1024,
+            index
+                .schema_version()
+                .map_or("unknown".to_string(), |version| version.to_string())
+        );
+    }
+
+    Ok(ExitCode::SUCCESS)
 }
 
-#[derive(Debug, Deserialize)]
-struct Params {
-    q: String,
-    page: Option<u32>,
+/// Makes the previous generation current, a running server picks it up on SIGHUP.
+fn rollback(index_dir: &Path) -> Result<ExitCode, Box<dyn Error>> {
+    let generation = Generations::new(index_dir).rollback()?;
+    println!("Rolled back to generation {}", generation);
+
+    Ok(ExitCode::SUCCESS)
 }
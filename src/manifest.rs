@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/// File of the manifest in the index directory
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Last indexed state of every source, used to re-index only the changed documents.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Sources by their name
+    pub sources: BTreeMap<String, SourceManifest>,
+}
+
+/// Documents of a source in the indexes, ids mapped to content hashes.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceManifest {
+    /// Unix timestamp of the last indexing
+    pub indexed_at: u64,
+    pub pages: BTreeMap<String, String>,
+    pub code_blocks: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    /// Loads the manifest of the index directory, a missing manifest is empty.
+    pub fn load(index_dir: &Path) -> io::Result<Manifest> {
+        let buf = match fs::read_to_string(index_dir.join(MANIFEST_FILE)) {
+            Ok(buf) => buf,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(err) => return Err(err),
+        };
+
+        serde_json::from_str(&buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Saves the manifest into the index directory. It's written to a temporary file
+    /// first, so an interrupted save keeps the previous manifest.
+    pub fn save(&self, index_dir: &Path) -> io::Result<()> {
+        let path = index_dir.join(MANIFEST_FILE);
+        let tmp_path = path.with_extension("json.tmp");
+
+        fs::write(&tmp_path, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+}
+
+impl SourceManifest {
+    pub fn new() -> Self {
+        Self {
+            indexed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        assert_eq!(Manifest::load(dir).unwrap(), Manifest::default());
+
+        let mut source = SourceManifest::new();
+        source
+            .pages
+            .insert("https://a/ch01.html".to_string(), "h1".to_string());
+        source
+            .code_blocks
+            .insert("https://a/ch01.html@0".to_string(), "h2".to_string());
+
+        let mut manifest = Manifest::default();
+        manifest.sources.insert("book".to_string(), source);
+        manifest.save(dir).unwrap();
+
+        assert_eq!(Manifest::load(dir).unwrap(), manifest);
+    }
+}
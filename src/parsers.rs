@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
-use html_escape::decode_html_entities;
 use lazy_static::lazy_static;
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
 use regex::Regex;
+use scraper::node::Element;
+use scraper::{ElementRef, Html, Selector};
 
 fn strip_tags(html: &str) -> String {
     lazy_static! {
@@ -11,34 +14,65 @@ fn strip_tags(html: &str) -> String {
     RE.replace_all(html, "").to_string()
 }
 
-fn strip_refs(text: &str) -> String {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"\[[^\]]*\]").unwrap();
-    }
-    RE.replace_all(text, "").to_string()
+/// CommonMark extensions enabled by mdBook
+fn md_options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES
 }
 
-fn strip_ticks(text: &str) -> String {
-    text.chars().filter(|&ch| ch != '`').collect()
+/// Language and edition of a code block from its info string.
+///
+/// Fenced blocks are Rust when the info string starts with `rust` or with a rustdoc
+/// attribute (`rust,ignore`, `no_run`), indented blocks don't have a language and are
+/// taken as Rust too. Blocks without a language or with plain text have none.
+fn code_block_lang(kind: &CodeBlockKind) -> (Option<String>, Option<u16>) {
+    const RUSTDOC_ATTRS: [&str; 7] = [
+        "ignore",
+        "no_run",
+        "should_panic",
+        "compile_fail",
+        "edition2015",
+        "edition2018",
+        "edition2021",
+    ];
+    const PLAIN_TEXT: [&str; 5] = ["", "text", "txt", "plain", "output"];
+
+    let info = match kind {
+        CodeBlockKind::Indented => return (Some(RUST.to_string()), None),
+        CodeBlockKind::Fenced(info) => info,
+    };
+
+    let attrs: Vec<&str> = info
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .collect();
+    let lang = attrs.first().unwrap_or(&"").to_lowercase();
+
+    let lang = if lang == RUST || RUSTDOC_ATTRS.contains(&lang.as_str()) {
+        Some(RUST.to_string())
+    } else if PLAIN_TEXT.contains(&lang.as_str()) {
+        None
+    } else {
+        Some(lang)
+    };
+
+    let edition = attrs.iter().find_map(|attr| parse_edition(attr));
+
+    (lang, edition)
 }
 
-fn strip_nav(text: &str) -> String {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"(?s)<nav .*?</nav>").unwrap();
-    }
-    RE.replace_all(text, "").to_string()
+/// Edition of a rustdoc attribute or a class, `edition2021` is 2021.
+fn parse_edition(attr: &str) -> Option<u16> {
+    attr.strip_prefix("edition")?.parse().ok()
 }
 
-fn parse_html_title(html: &str) -> Option<String> {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"<title>(.*?)</title>").unwrap();
-    }
-    if let Some(captures) = RE.captures(html) {
-        if let Some(title) = captures.get(1) {
-            return Some(title.as_str().to_string());
-        }
+/// Terminates the current line, unless it's already terminated.
+fn end_line(s: &mut String) {
+    if !s.is_empty() && !s.ends_with('\n') {
+        s.push('\n');
     }
-    None
 }
 
 fn seek_link_description(s: &str) -> &str {
@@ -48,135 +82,687 @@ fn seek_link_description(s: &str) -> &str {
     s
 }
 
-fn parse_include(s: &str, md_dir: &str) -> Option<String> {
-    let toks: Vec<_> = s
-        .split(' ')
-        .filter(|&x| x == "#include" || x == "#rustdoc_include")
-        .collect();
+/// Maximal depth of nested includes, the same as in mdBook
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Expands mdBook links: `{{#include}}`, `{{#rustdoc_include}}` and `{{#playground}}` are
+/// replaced with the referenced file, `{{#title}}` is dropped. Links are expanded in the
+/// included files as well, `stack` holds the files being included to break cycles.
+fn expand_links(s: &str, dir: &Path, stack: &mut Vec<PathBuf>) -> String {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?x)
+            \\\{\{\#.*?\}\}           # escaped link
+            |
+            \{\{\s*\#([a-zA-Z0-9_]+)  # link type
+            \s*([^}]*)               # path and properties
+            \}\}"
+        )
+        .unwrap();
+    }
 
-    if !toks.is_empty() {
-        let filename = s.replace("{{", "").replace("}}", "").replace(toks[0], "");
+    let mut expanded = String::new();
+    let mut last = 0;
 
-        let filename = filename.trim();
+    for captures in RE.captures_iter(s) {
+        let link = captures.get(0).unwrap();
+        expanded.push_str(&s[last..link.start()]);
+        last = link.end();
 
-        if !filename.is_empty() {
-            // ../src/main.rs:anchor
-            let path = PathBuf::from(md_dir).join(filename.split(':').next().unwrap());
-            if let Ok(buf) = std::fs::read_to_string(&path) {
-                let code: Vec<_> = buf
-                    .lines()
-                    .filter(|&line| !(line.starts_with("// ") || line.eq("//")))
-                    .collect();
-                return Some(code.join("\n"));
-            }
+        let (Some(link_type), Some(target)) = (captures.get(1), captures.get(2)) else {
+            // Escaped link, drop the backslash
+            expanded.push_str(&link.as_str()[1..]);
+            continue;
+        };
 
-            eprintln!("Couldn't open {:?}", path);
+        match link_type.as_str() {
+            "include" | "rustdoc_include" | "playground" => {
+                if let Some(buf) = parse_include(link_type.as_str(), target.as_str(), dir, stack) {
+                    expanded.push_str(&buf);
+                }
+            }
+            "title" => {}
+            _ => expanded.push_str(link.as_str()),
         }
     }
-    None
+
+    expanded.push_str(&s[last..]);
+    expanded
 }
 
-pub fn parse_summary_md(s: &str) -> Vec<(String, String)> {
-    s.split('\n')
-        .map(|x| x.trim())
-        .filter(|x| !x.is_empty() && !x.contains("<!--"))
-        .map(|x| x.split("](").collect())
-        .filter(|x: &Vec<&str>| x.len() == 2)
-        .map(|x: Vec<&str>| {
-            (
-                seek_link_description(x[0]).to_string(),
-                x[1].replace(".md)", ""),
-            )
-        })
-        .map(|x: (String, String)| {
-            if x.1.starts_with("./") {
-                return (x.0, x.1[2..].to_string());
+/// Reads the file referenced by an include link: `file.rs`, `file.rs:anchor`,
+/// `file.rs:2`, `file.rs:2:10`, `file.rs::10` or `file.rs:2:`. Lines outside of the
+/// anchor or range are hidden from readers with `{{#rustdoc_include}}`, so they're
+/// skipped just like with `{{#include}}`.
+fn parse_include(
+    link_type: &str,
+    target: &str,
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+) -> Option<String> {
+    let mut props = target.split_whitespace();
+    // ../src/main.rs:anchor
+    let spec = props.next()?;
+    let (filename, selector) = match spec.split_once(':') {
+        Some((filename, selector)) => (filename, Some(selector)),
+        None => (spec, None),
+    };
+
+    let path = dir.join(filename);
+    let canonical_path = path.canonicalize().unwrap_or_else(|_| path.clone());
+
+    if stack.contains(&canonical_path) || stack.len() >= MAX_INCLUDE_DEPTH {
+        eprintln!("Skipped recursive include of {:?}", path);
+        return None;
+    }
+
+    let Ok(buf) = std::fs::read_to_string(&path) else {
+        eprintln!("Couldn't open {:?}", path);
+        return None;
+    };
+
+    let buf = select_lines(&buf, selector);
+
+    stack.push(canonical_path);
+    let buf = expand_links(&buf, path.parent().unwrap_or(dir), stack);
+    stack.pop();
+
+    if link_type == "playground" {
+        let attrs: Vec<_> = props.collect();
+        let info = match attrs.is_empty() {
+            true => "rust".to_string(),
+            false => format!("rust,{}", attrs.join(",")),
+        };
+        return Some(format!("```{}\n{}\n```", info, buf.trim_end()));
+    }
+
+    Some(buf)
+}
+
+/// Selects the lines of an anchor or a range of lines (numbered from 1, the end is
+/// inclusive). A single line number selects a single line. Lines with anchor markers
+/// (`ANCHOR: name`, `ANCHOR_END: name`) are never selected.
+fn select_lines(s: &str, selector: Option<&str>) -> String {
+    lazy_static! {
+        static ref ANCHOR_START: Regex = Regex::new(r"ANCHOR:\s*(?P<name>[\w_-]+)").unwrap();
+        static ref ANCHOR_END: Regex = Regex::new(r"ANCHOR_END:\s*(?P<name>[\w_-]+)").unwrap();
+    }
+
+    let is_marker = |line: &str| ANCHOR_START.is_match(line) || ANCHOR_END.is_match(line);
+
+    let mut parts = selector.unwrap_or_default().splitn(3, ':');
+    let start = parts.next().unwrap_or_default();
+
+    let range = match (start.parse::<usize>(), parts.next()) {
+        (Ok(start), None) => start.saturating_sub(1)..start,
+        (Ok(start), Some(end)) => start.saturating_sub(1)..end.parse().unwrap_or(usize::MAX),
+        (Err(_), end) if start.is_empty() => {
+            0..end.and_then(|end| end.parse().ok()).unwrap_or(usize::MAX)
+        }
+        (Err(_), _) => {
+            let mut in_anchor = false;
+            let mut lines = vec![];
+
+            for line in s.lines() {
+                if in_anchor {
+                    match ANCHOR_END.captures(line) {
+                        Some(captures) if &captures["name"] == start => break,
+                        _ if !is_marker(line) => lines.push(line),
+                        _ => {}
+                    }
+                } else if let Some(captures) = ANCHOR_START.captures(line) {
+                    in_anchor = &captures["name"] == start;
+                }
+            }
+
+            return lines.join("\n");
+        }
+    };
+
+    let lines: Vec<_> = s
+        .lines()
+        .enumerate()
+        .filter(|(line_no, line)| range.contains(line_no) && !is_marker(line))
+        .map(|(_, line)| line)
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Drops lines hidden from readers in Rust code blocks, as rustdoc does: the ones which
+/// are `#` or start with `# ` once trimmed. `##` at the beginning of lines is unescaped,
+/// other lines starting with `#` (`#[derive]`, `#foo`) are kept.
+fn strip_hidden_lines(code: &str) -> String {
+    let lines: Vec<_> = code
+        .lines()
+        .filter_map(|line| {
+            let trimmed = line.trim();
+            let indent = line.len() - line.trim_start().len();
+
+            if let Some(rest) = line[indent..].strip_prefix("##") {
+                Some(format!("{}#{}", &line[..indent], rest))
+            } else if trimmed == "#" || trimmed.starts_with("# ") || trimmed.starts_with("#\t") {
+                None
+            } else {
+                Some(line.to_string())
             }
-            x
         })
-        .filter(|x| !(x.0.is_empty() || x.1.is_empty()))
+        .collect();
+
+    lines.join("\n")
+}
+
+/// Chapters listed in the summary, as titles and urls relative to the book.
+pub fn parse_summary_md(s: &str) -> Vec<(String, String)> {
+    parse_summary_chapters(s)
+        .into_iter()
+        .map(|chapter| (chapter.title, chapter.rel_url))
         .collect()
 }
 
-pub fn parse_md_page(s: &str, md_dir: &str) -> (String, Vec<String>) {
-    let mut new_s = String::new();
-    let mut code_blocks: Vec<String> = vec![];
-    let mut code = String::new();
-    let mut in_code = false;
+/// A chapter listed in the summary.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SummaryChapter {
+    pub title: String,
+    /// Url relative to the book, without the `.md` extension
+    pub rel_url: String,
+    /// Titles of the parent chapters and of the chapter, the nesting of the list items
+    pub path: Vec<String>,
+}
+
+/// Chapters listed in the summary, with their place in the tree of chapters.
+pub fn parse_summary_chapters(s: &str) -> Vec<SummaryChapter> {
+    let mut chapters = vec![];
+    // Indentation and title of the chapters enclosing the current line
+    let mut parents: Vec<(usize, String)> = vec![];
 
-    // Handle includes
     for line in s.split('\n') {
-        if let Some(line_stripped) = line.strip_prefix("{{") {
-            if let Some(buf) = parse_include(line_stripped, md_dir) {
-                new_s.push_str(&buf);
-                new_s.push('\n');
-            }
+        let Some((title, rel_url)) = parse_summary_line(line.trim()) else {
             continue;
+        };
+
+        let indent = line.len() - line.trim_start().len();
+        while parents.last().is_some_and(|(parent, _)| *parent >= indent) {
+            parents.pop();
+        }
+
+        let mut path: Vec<String> = parents.iter().map(|(_, title)| title.clone()).collect();
+        path.push(title.clone());
+        parents.push((indent, title.clone()));
+
+        // Draft chapters don't have a file
+        if !title.is_empty() && !rel_url.is_empty() {
+            chapters.push(SummaryChapter {
+                title,
+                rel_url,
+                path,
+            });
         }
+    }
+
+    chapters
+}
 
-        new_s.push_str(line);
-        new_s.push('\n');
+/// Title and relative url of the link in the summary line `- [Title](dir/page.md)`.
+fn parse_summary_line(line: &str) -> Option<(String, String)> {
+    if line.is_empty() || line.contains("<!--") {
+        return None;
     }
 
-    let s = new_s;
-    let mut new_s = String::new();
+    let x: Vec<&str> = line.split("](").collect();
+    if x.len() != 2 {
+        return None;
+    }
 
-    for line in s.split('\n') {
-        // Code block start/end
-        if line.starts_with("```") {
-            if in_code {
-                if !code.is_empty() {
-                    code_blocks.push(code.trim().to_string());
+    let title = seek_link_description(x[0]).to_string();
+    // Draft chapters are linked with `()`
+    let rel_url = match x[1] {
+        ")" => String::new(),
+        url => url.replace(".md)", ""),
+    };
+    let rel_url = match rel_url.strip_prefix("./") {
+        Some(rel_url) => rel_url.to_string(),
+        None => rel_url,
+    };
+
+    Some((title, rel_url))
+}
+
+/// Language of Rust code blocks
+pub const RUST: &str = "rust";
+
+/// A code block of a page.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CodeBlock {
+    /// Language from the info string or the class, ie. `rust` or `toml`
+    pub lang: String,
+    /// Edition of Rust code blocks, when it's set
+    pub edition: Option<u16>,
+    pub code: String,
+}
+
+impl CodeBlock {
+    pub fn rust(code: &str) -> Self {
+        Self {
+            lang: RUST.to_string(),
+            edition: None,
+            code: code.to_string(),
+        }
+    }
+
+    pub fn is_rust(&self) -> bool {
+        self.lang == RUST
+    }
+}
+
+/// Part of a page introduced by a heading.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Section {
+    /// Heading text, empty for the content preceding the first heading
+    pub heading: String,
+    /// Id of the heading as generated by mdBook, empty for the content preceding the
+    /// first heading
+    pub anchor: String,
+    pub body: String,
+    /// Code blocks with a language, other languages than Rust are in the body too
+    pub code_blocks: Vec<CodeBlock>,
+}
+
+impl Section {
+    fn is_empty(&self) -> bool {
+        self.heading.is_empty() && self.body.trim().is_empty() && self.code_blocks.is_empty()
+    }
+}
+
+/// Returns the page text (with headings) and Rust code blocks.
+pub fn parse_md_page(s: &str, md_dir: &str) -> (String, Vec<String>) {
+    let mut body = String::new();
+    let mut code_blocks = vec![];
+
+    for section in split_md_sections(s, md_dir) {
+        if !section.heading.is_empty() {
+            body.push_str(&section.heading);
+            body.push('\n');
+        }
+        body.push_str(&section.body);
+        end_line(&mut body);
+        code_blocks.extend(
+            section
+                .code_blocks
+                .into_iter()
+                .filter(CodeBlock::is_rust)
+                .map(|code_block| code_block.code),
+        );
+    }
+
+    (clean_body(&body), code_blocks)
+}
+
+/// Splits the page at headings into sections.
+pub fn parse_md_sections(s: &str, md_dir: &str) -> Vec<Section> {
+    let mut sections = split_md_sections(s, md_dir);
+
+    for section in sections.iter_mut() {
+        section.body = clean_body(&section.body);
+    }
+
+    sections
+}
+
+/// Drops whitespace left by the markup, but keeps single empty lines.
+fn clean_body(body: &str) -> String {
+    let mut lines: Vec<&str> = vec![];
+
+    for line in body.lines().map(|line| line.trim()) {
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+
+    lines.join("\n").trim().to_string()
+}
+
+fn split_md_sections(s: &str, md_dir: &str) -> Vec<Section> {
+    let new_s = expand_links(s, Path::new(md_dir), &mut vec![]);
+
+    let mut sections = vec![];
+    let mut section = Section::default();
+    let mut anchors = HashMap::new();
+    // Heading being read and its custom id (`# Heading {#id}`)
+    let mut heading: Option<(String, Option<String>)> = None;
+    // Code block being read: its text, language and edition
+    let mut code: Option<(String, Option<String>, Option<u16>)> = None;
+    let mut in_image = 0;
+
+    for event in Parser::new_ext(&new_s, md_options()) {
+        let body = match &mut heading {
+            Some((heading, _)) => heading,
+            None => &mut section.body,
+        };
+
+        match event {
+            Event::Start(Tag::Heading { id, .. }) => {
+                if !section.is_empty() {
+                    sections.push(section);
                 }
-                code.clear();
-                in_code = false;
-                continue;
+                section = Section::default();
+                heading = Some((String::new(), id.map(|id| id.to_string())));
             }
-            if line.starts_with("```rust") {
-                in_code = true;
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((text, id)) = heading.take() {
+                    let text = text.trim().to_string();
+                    section.anchor = match id {
+                        Some(id) => id,
+                        None => unique_anchor(&text, &mut anchors),
+                    };
+                    section.heading = text;
+                }
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                end_line(body);
+                let (lang, edition) = code_block_lang(&kind);
+                code = Some((String::new(), lang, edition));
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                if let Some((text, lang, edition)) = code.take() {
+                    let is_rust = lang.as_deref() == Some(RUST);
+
+                    // Other languages (shell, toml, output) stay searchable as prose
+                    if !is_rust {
+                        body.push_str(&text);
+                    }
+
+                    let text = match is_rust {
+                        true => strip_hidden_lines(&text),
+                        false => text,
+                    };
+                    let text = text.trim_start_matches('\n').trim_end();
+
+                    if let Some(lang) = lang.filter(|_| !text.is_empty()) {
+                        section.code_blocks.push(CodeBlock {
+                            lang,
+                            edition,
+                            code: text.to_string(),
+                        });
+                    }
+                    // Keep an empty line in place of the code block
+                    end_line(body);
+                    body.push('\n');
+                }
+            }
+            // Nested lists start right after the text of the parent item
+            Event::Start(Tag::List(_)) => end_line(body),
+            Event::Start(Tag::Image { .. }) => in_image += 1,
+            Event::End(TagEnd::Image) => in_image -= 1,
+            Event::Text(text) => match &mut code {
+                Some((code, _, _)) => code.push_str(&text),
+                None if in_image == 0 => body.push_str(&text),
+                None => {}
+            },
+            Event::Code(text) | Event::InlineMath(text) | Event::DisplayMath(text) => {
+                body.push_str(&text)
+            }
+            Event::Html(html) | Event::InlineHtml(html) => body.push_str(&strip_tags(&html)),
+            Event::SoftBreak | Event::HardBreak => body.push('\n'),
+            Event::End(TagEnd::TableCell) => body.push(' '),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Item
+                | TagEnd::TableHead
+                | TagEnd::TableRow
+                | TagEnd::HtmlBlock
+                | TagEnd::FootnoteDefinition
+                | TagEnd::DefinitionListTitle
+                | TagEnd::DefinitionListDefinition,
+            ) => end_line(body),
+            _ => {}
+        }
+    }
+
+    if !section.is_empty() {
+        sections.push(section);
+    }
+
+    sections
+}
+
+/// Id of a heading, generated the way mdBook does it: lowercase alphanumerics, `_` and
+/// `-`, whitespace replaced with `-`, and a counter appended to repeated ids.
+fn unique_anchor(heading: &str, anchors: &mut HashMap<String, usize>) -> String {
+    let anchor: String = heading
+        .chars()
+        .filter_map(|ch| {
+            if ch.is_alphanumeric() || ch == '_' || ch == '-' {
+                Some(ch.to_ascii_lowercase())
+            } else if ch.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let count = anchors.entry(anchor.clone()).or_insert(0);
+    let anchor = match *count {
+        0 => anchor,
+        count => format!("{anchor}-{count}"),
+    };
+    *count += 1;
+
+    anchor
+}
+
+/// Returns the page text, code blocks and title.
+pub fn parse_html_page(html: &str) -> (String, Vec<CodeBlock>, Option<String>) {
+    lazy_static! {
+        static ref MAIN: Selector = Selector::parse("main").unwrap();
+        static ref BODY: Selector = Selector::parse("body").unwrap();
+    }
+
+    let document = Html::parse_document(html);
+    let root = document
+        .select(&MAIN)
+        .next()
+        .or_else(|| document.select(&BODY).next())
+        .unwrap_or_else(|| document.root_element());
+
+    let mut text = String::new();
+    let mut code_blocks = vec![];
+
+    walk_html(root, &mut text, &mut code_blocks, false);
+
+    (
+        clean_body(&text),
+        code_blocks,
+        parse_html_title(&document, root),
+    )
+}
+
+/// Scripts, styles, navigation and other page chrome
+fn is_html_chrome(element: &Element) -> bool {
+    const CHROME: [&str; 9] = [
+        "script", "style", "noscript", "template", "nav", "header", "footer", "button", "svg",
+    ];
+
+    // mdBook's menu bar sits outside of <nav>
+    CHROME.contains(&element.name()) || element.id() == Some("menu-bar")
+}
+
+/// Collects the text of the element and its descendants, except for chrome
+/// (scripts, styles, navigation), and code blocks. Only Rust code is left out of
+/// the text.
+fn walk_html(
+    element: ElementRef,
+    text: &mut String,
+    code_blocks: &mut Vec<CodeBlock>,
+    preformatted: bool,
+) {
+    const BLOCKS: [&str; 22] = [
+        "p",
+        "div",
+        "section",
+        "article",
+        "main",
+        "aside",
+        "blockquote",
+        "pre",
+        "ul",
+        "ol",
+        "li",
+        "dl",
+        "dt",
+        "dd",
+        "table",
+        "tr",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+    ];
+
+    for child in element.children() {
+        if let Some(t) = child.value().as_text() {
+            if preformatted {
+                text.push_str(t);
+            } else if !(t.trim().is_empty() && (text.is_empty() || text.ends_with('\n'))) {
+                // Outside of <pre> whitespace collapses into a single space
+                let words: Vec<_> = t.split_whitespace().collect();
+                if t.starts_with(char::is_whitespace) {
+                    text.push(' ');
+                }
+                text.push_str(&words.join(" "));
+                if !words.is_empty() && t.ends_with(char::is_whitespace) {
+                    text.push(' ');
+                }
             }
             continue;
         }
 
-        // Trim the end but not the beginning to keep indentation
-        let line = line.trim_end();
+        let Some(child) = ElementRef::wrap(child) else {
+            continue;
+        };
+        let name = child.value().name();
 
-        if in_code {
-            code.push_str(line);
-            code.push('\n');
+        if is_html_chrome(child.value()) {
             continue;
         }
 
-        // Skip underlines
-        if line.starts_with("---") || line.starts_with("___") || line.starts_with('[') {
+        if name == "pre" {
+            if let Some(code_block) = parse_html_code(child) {
+                let is_rust = code_block.is_rust();
+                if !code_block.code.is_empty() {
+                    code_blocks.push(code_block);
+                }
+                if is_rust {
+                    // Keep an empty line in place of the code block
+                    end_line(text);
+                    text.push('\n');
+                    continue;
+                }
+            }
+        }
+
+        if name == "br" {
+            text.push('\n');
             continue;
         }
 
-        // Trim leading special markup chars
-        let line = line.trim_start_matches('#').trim_start();
-        let line = line.trim_start_matches('*').trim_start();
+        let is_block = BLOCKS.contains(&name);
+        if is_block {
+            end_line(text);
+        }
 
-        // Strip "decorators"
-        let line = &strip_tags(line);
-        let line = &strip_refs(line);
-        let line = &strip_ticks(line);
+        walk_html(child, text, code_blocks, preformatted || name == "pre");
 
-        new_s.push_str(line);
-        new_s.push('\n');
+        if is_block {
+            end_line(text);
+        } else if name == "td" || name == "th" {
+            text.push(' ');
+        }
+    }
+}
+
+/// Code block of `<pre><code class="language-rust edition2021">`, without the lines
+/// mdBook hides from readers (`<span class="boring">`). The parser already decoded
+/// the entities.
+fn parse_html_code(pre: ElementRef) -> Option<CodeBlock> {
+    lazy_static! {
+        static ref CODE: Selector = Selector::parse("code").unwrap();
     }
 
-    (new_s.trim().to_string(), code_blocks)
+    let code = pre.select(&CODE).next()?;
+
+    let lang = code
+        .value()
+        .classes()
+        .find_map(|class| class.strip_prefix("language-"))?
+        .to_lowercase();
+    let edition = code.value().classes().find_map(parse_edition);
+
+    let mut text = String::new();
+
+    for node in code.descendants() {
+        let hidden = node.ancestors().any(|ancestor| {
+            ancestor
+                .value()
+                .as_element()
+                .is_some_and(|element| element.classes().any(|class| class == "boring"))
+        });
+
+        if let Some(t) = node.value().as_text() {
+            if !hidden {
+                text.push_str(t);
+            }
+        }
+    }
+
+    Some(CodeBlock {
+        lang,
+        edition,
+        code: text.trim_start_matches('\n').trim_end().to_string(),
+    })
 }
 
-pub fn parse_html_page(html: &str) -> (String, Vec<String>, Option<String>) {
-    // todo: parse code blocks
-    let title = parse_html_title(html);
+/// Title of the page, from `<title>` unless it's generic. A title is generic when it's
+/// empty or when it doesn't have a page specific part (`Page - Book`), and then the
+/// first `<h1>` is used instead.
+fn parse_html_title(document: &Html, root: ElementRef) -> Option<String> {
+    lazy_static! {
+        static ref TITLE: Selector = Selector::parse("title").unwrap();
+        static ref H1: Selector = Selector::parse("h1").unwrap();
+    }
+
+    let title = document
+        .select(&TITLE)
+        .next()
+        .map(|title| collapse_whitespace(&title.text().collect::<String>()))
+        .filter(|title| !title.is_empty());
+
+    let h1 = root
+        .select(&H1)
+        .find(|h1| {
+            !h1.ancestors()
+                .filter_map(|ancestor| ancestor.value().as_element())
+                .any(is_html_chrome)
+        })
+        .map(|h1| collapse_whitespace(&h1.text().collect::<String>()))
+        .filter(|h1| !h1.is_empty());
 
-    let text = strip_nav(html);
-    let text = strip_tags(&text);
-    let text = decode_html_entities(&text).to_string();
+    let is_generic =
+        |title: &String| !title.contains(" - ") && !title.contains(" | ") && !title.contains(" — ");
+
+    match (title, h1) {
+        (Some(title), Some(h1)) if is_generic(&title) => Some(h1),
+        (None, h1) => h1,
+        (title, _) => title,
+    }
+}
 
-    (text, vec![], title)
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
 }
 
 #[cfg(test)]
@@ -311,4 +897,372 @@ Head2
 content2"
         );
     }
+
+    #[test]
+    fn test_parse_page_code_block_kinds() {
+        let (body, code_blocks) = parse_md_page(
+            "
+Intro:
+
+  ```rust
+  let indented_fence = 1;
+  ```
+
+~~~rust
+let tilde = 2;
+~~~
+
+    let indented = 3;
+
+```toml
+[dependencies]
+```
+            ",
+            "",
+        );
+
+        assert_eq!(
+            code_blocks,
+            vec![
+                "let indented_fence = 1;",
+                "let tilde = 2;",
+                "let indented = 3;"
+            ]
+        );
+        assert!(body.contains("[dependencies]"));
+        assert!(!body.contains("let"));
+    }
+
+    #[test]
+    fn test_parse_page_inline_markup() {
+        let (body, _) = parse_md_page(
+            "
+[`Box<T>`](ch15-01-box.html) is a *smart* **pointer**, see `Deref`.
+
+| Type | Size |
+|------|------|
+| u8   | 1    |
+
+[ref]: https://doc.rust-lang.org
+            ",
+            "",
+        );
+
+        assert_eq!(
+            body,
+            "Box<T> is a smart pointer, see Deref.
+Type Size
+u8 1"
+        );
+    }
+
+    #[test]
+    fn test_parse_page_nested_lists() {
+        let (body, _) = parse_md_page(
+            "
+- Ownership
+  - Borrowing
+  - Moves
+- Lifetimes
+            ",
+            "",
+        );
+
+        assert_eq!(body, "Ownership\nBorrowing\nMoves\nLifetimes");
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let sections = parse_md_sections(
+            "
+Intro
+# Using `Box<T>` to Point to Data on the Heap
+Boxes
+```rust
+let b = Box::new(5);
+```
+## Example
+First
+## Example
+Second
+## Custom {#custom-id}
+            ",
+            "",
+        );
+
+        let headings: Vec<_> = sections
+            .iter()
+            .map(|s| (s.heading.as_str(), s.anchor.as_str(), s.body.as_str()))
+            .collect();
+
+        assert_eq!(
+            headings,
+            vec![
+                ("", "", "Intro"),
+                (
+                    "Using Box<T> to Point to Data on the Heap",
+                    "using-boxt-to-point-to-data-on-the-heap",
+                    "Boxes"
+                ),
+                ("Example", "example", "First"),
+                ("Example", "example-1", "Second"),
+                ("Custom", "custom-id", ""),
+            ]
+        );
+        assert_eq!(
+            sections[1].code_blocks,
+            vec![CodeBlock::rust("let b = Box::new(5);")]
+        );
+    }
+
+    #[test]
+    fn test_parse_code_block_languages() {
+        let sections = parse_md_sections(
+            "
+```rust,edition2018
+let x = 1;
+```
+
+```toml
+[dependencies]
+```
+
+```text
+Hello, world!
+```
+            ",
+            "",
+        );
+
+        assert_eq!(
+            sections[0].code_blocks,
+            vec![
+                CodeBlock {
+                    lang: "rust".to_string(),
+                    edition: Some(2018),
+                    code: "let x = 1;".to_string(),
+                },
+                CodeBlock {
+                    lang: "toml".to_string(),
+                    edition: None,
+                    code: "[dependencies]".to_string(),
+                },
+            ]
+        );
+        assert!(sections[0].body.contains("[dependencies]"));
+        assert!(sections[0].body.contains("Hello, world!"));
+    }
+
+    #[test]
+    fn test_parse_summary_chapters() {
+        let chapters = parse_summary_chapters(
+            "
+# Summary
+
+[Introduction](intro.md)
+
+# Basics
+
+- [Getting Started](ch01-00-getting-started.md)
+    - [Installation](ch01-01-installation.md)
+        - [Draft]()
+            - [Deep](deep.md)
+    - [Hello, World!](ch01-02-hello-world.md)
+- [Guessing Game](ch02-00-guessing-game-tutorial.md)",
+        );
+
+        let paths: Vec<_> = chapters
+            .iter()
+            .map(|chapter| (chapter.rel_url.as_str(), chapter.path.join(" > ")))
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![
+                ("intro", "Introduction".to_string()),
+                ("ch01-00-getting-started", "Getting Started".to_string()),
+                (
+                    "ch01-01-installation",
+                    "Getting Started > Installation".to_string()
+                ),
+                (
+                    "deep",
+                    "Getting Started > Installation > Draft > Deep".to_string()
+                ),
+                (
+                    "ch01-02-hello-world",
+                    "Getting Started > Hello, World!".to_string()
+                ),
+                (
+                    "ch02-00-guessing-game-tutorial",
+                    "Guessing Game".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_html_page() {
+        let (body, code_blocks, title) = parse_html_page(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Effective Rust</title>
+    <style>body { color: red; }</style>
+    <script>var path_to_root = "";</script>
+</head>
+<body>
+    <nav id="sidebar"><ol><li>Item 1</li></ol></nav>
+    <div id="menu-bar"><h1 class="menu-title">Effective Rust</h1></div>
+    <main>
+        <h1>Item 1: Use the type system</h1>
+        <p>Prefer <code>Option&lt;T&gt;</code> &amp; enums.</p>
+        <pre><code class="language-rust"><span class="boring">fn main() {
+</span>let v: Vec&lt;u8&gt; = Vec::new();
+<span class="boring">}
+</span></code></pre>
+        <pre><code class="language-bash">cargo build</code></pre>
+    </main>
+    <footer>Copyright</footer>
+    <script>window.playground_copyable = true;</script>
+</body>
+</html>"#,
+        );
+
+        assert_eq!(title.as_deref(), Some("Item 1: Use the type system"));
+        assert_eq!(
+            body,
+            "Item 1: Use the type system
+Prefer Option<T> & enums.
+
+cargo build"
+        );
+        assert_eq!(
+            code_blocks,
+            vec![
+                CodeBlock::rust("let v: Vec<u8> = Vec::new();"),
+                CodeBlock {
+                    lang: "bash".to_string(),
+                    edition: None,
+                    code: "cargo build".to_string(),
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_html_title() {
+        let (_, _, title) = parse_html_page(
+            "<html><head><title>Closures - Effective Rust</title></head>
+            <body><h1>Item 2</h1></body></html>",
+        );
+        assert_eq!(title.as_deref(), Some("Closures - Effective Rust"));
+
+        let (_, _, title) = parse_html_page("<html><body><p>No title</p></body></html>");
+        assert_eq!(title, None);
+    }
+
+    fn write_listings(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (file_name, content) in files {
+            let path = dir.path().join(file_name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_parse_include_anchors_and_ranges() {
+        let dir = write_listings(&[(
+            "listing/main.rs",
+            "use std::io;
+// ANCHOR: here
+fn main() {
+    // ANCHOR: inner
+    let x = 5;
+    // ANCHOR_END: inner
+}
+// ANCHOR_END: here
+// The end",
+        )]);
+
+        let (_, code_blocks) = parse_md_page(
+            "
+```rust
+{{#include listing/main.rs:here}}
+```
+```rust
+{{#include listing/main.rs:inner}}
+```
+```rust
+{{#include listing/main.rs:1}}
+```
+```rust
+{{#rustdoc_include listing/main.rs:3:5}}
+```
+```rust
+{{#include listing/main.rs::1}}
+{{#include listing/main.rs:9:}}
+```
+            ",
+            dir.path().to_str().unwrap(),
+        );
+
+        assert_eq!(
+            code_blocks,
+            vec![
+                "fn main() {\n    let x = 5;\n}",
+                "    let x = 5;",
+                "use std::io;",
+                "fn main() {\n    let x = 5;",
+                "use std::io;\n// The end",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_include_nested_and_playground() {
+        let dir = write_listings(&[
+            ("a.md", "A\n{{#include b/b.md}}"),
+            ("b/b.md", "B\n{{#include ../a.md}}"),
+            ("c.rs", "fn c() {}"),
+        ]);
+
+        let (body, code_blocks) = parse_md_page(
+            "{{#include a.md}}\n\n{{#playground c.rs editable}}\n\n\\{{#include a.md}}",
+            dir.path().to_str().unwrap(),
+        );
+
+        assert_eq!(body, "A\nB\n\n{{#include a.md}}");
+        assert_eq!(code_blocks, vec!["fn c() {}"]);
+    }
+
+    #[test]
+    fn test_strip_hidden_lines() {
+        let (_, code_blocks) = parse_md_page(
+            "
+```rust
+# use std::collections::HashMap;
+#[derive(Debug)]
+struct S;
+#
+## not hidden
+#![allow(unused)]
+# fn main() {
+let s = S;
+#foo
+    # let hidden = 1;
+    ##
+# }
+```
+            ",
+            "",
+        );
+
+        assert_eq!(
+            code_blocks,
+            vec!["#[derive(Debug)]\nstruct S;\n# not hidden\n#![allow(unused)]\nlet s = S;\n#foo\n    #"]
+        );
+    }
 }
@@ -0,0 +1,386 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// A query typed by a user.
+///
+/// Words are matched in any field, the other parts of the syntax are:
+///
+/// - `"exact phrase"`, words next to each other
+/// - `-word` or `-"a phrase"`, documents without it
+/// - `title:word` or `title:"a phrase"`, in chapter titles and headings
+/// - `book:nomicon`, in the source with the name, `-book:nomicon` not in it
+/// - `lang:toml`, in code blocks of the language, `-lang:toml` not of it
+/// - `code:`, only in code blocks, `code:word` looks for the word in code blocks
+///
+/// Parsing never fails, the parts which make no sense are ignored and reported.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct UserQuery {
+    pub words: Vec<String>,
+    pub phrases: Vec<String>,
+    /// Excluded words and phrases
+    pub excluded: Vec<String>,
+    pub filters: Vec<Filter>,
+    /// Search only code blocks
+    pub code: bool,
+    /// Parts of the query which were ignored
+    pub ignored: Vec<String>,
+}
+
+/// Restriction of the searched documents to the ones with a value of a field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub field: FilterField,
+    pub value: String,
+    /// The documents must not have the value
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterField {
+    /// Chapter title or section heading, `title:`
+    Title,
+    /// Name of the source, `book:`
+    Book,
+    /// Language of a code block, `lang:`
+    Lang,
+}
+
+impl UserQuery {
+    pub fn parse(q: &str) -> Self {
+        let mut query = UserQuery::default();
+        let mut chars = q.char_indices().peekable();
+
+        while let Some(&(start, c)) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+
+            // `-word` or `-"phrase"`, but not an operator like `->`
+            let negated = c == '-'
+                && q[start + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|next| next.is_alphanumeric() || next == '"');
+            if negated {
+                chars.next();
+            }
+
+            // `name:` of a filter, names of paths like `std::fmt` aren't filters
+            let name = read_filter_name(q, &mut chars);
+
+            let Some(value) = read_value(q, &mut chars, &mut query.ignored) else {
+                continue;
+            };
+
+            match name.as_deref() {
+                None if value.is_empty() => query.ignored.push("\"\"".to_string()),
+                None => query.add_text(value, negated),
+                Some("code") => {
+                    query.code = true;
+                    if negated {
+                        query.ignored.push("-".to_string());
+                    }
+                    if !value.is_empty() {
+                        query.add_text(value, false);
+                    }
+                }
+                Some(name) => {
+                    let field = match name {
+                        "title" => FilterField::Title,
+                        "book" => FilterField::Book,
+                        "lang" => FilterField::Lang,
+                        _ => {
+                            // An unknown field is ignored, its value is still searched
+                            query.ignored.push(format!("{}:", name));
+                            if !value.is_empty() {
+                                query.add_text(value, negated);
+                            }
+                            continue;
+                        }
+                    };
+
+                    if value.is_empty() {
+                        query.ignored.push(format!("{}:", name));
+                        continue;
+                    }
+
+                    let value = match field {
+                        FilterField::Title => value,
+                        FilterField::Book | FilterField::Lang => value.to_lowercase(),
+                    };
+                    query.filters.push(Filter {
+                        field,
+                        value,
+                        negated,
+                    });
+                }
+            }
+        }
+
+        query
+    }
+
+    fn add_text(&mut self, text: String, negated: bool) {
+        if negated {
+            self.excluded.push(text);
+        } else if text.contains(char::is_whitespace) {
+            self.phrases.push(text);
+        } else {
+            self.words.push(text);
+        }
+    }
+
+    /// The query restricted to the books too.
+    pub fn with_books(mut self, books: &BookFilter) -> Self {
+        let filters = books
+            .include
+            .iter()
+            .map(|name| (name, false))
+            .chain(books.exclude.iter().map(|name| (name, true)))
+            .map(|(name, negated)| Filter {
+                field: FilterField::Book,
+                value: name.clone(),
+                negated,
+            });
+        self.filters.extend(filters);
+        self
+    }
+
+    /// The query in all books.
+    pub fn without_books(&self) -> Self {
+        let mut query = self.clone();
+        query
+            .filters
+            .retain(|filter| filter.field != FilterField::Book);
+        query
+    }
+
+    /// Whether nothing is searched for.
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty() && self.phrases.is_empty() && self.filters.is_empty()
+    }
+
+    /// The words of the query, without the phrases and filters.
+    pub fn text(&self) -> String {
+        self.words.join(" ")
+    }
+
+    /// Searches only the code blocks, when asked to or when a language is required.
+    pub fn code_only(&self) -> bool {
+        self.code
+            || self
+                .filters
+                .iter()
+                .any(|filter| filter.field == FilterField::Lang && !filter.negated)
+    }
+
+    /// Filters of the field.
+    pub fn filters(&self, field: FilterField) -> impl Iterator<Item = &Filter> {
+        self.filters
+            .iter()
+            .filter(move |filter| filter.field == field)
+    }
+
+    /// All words and phrases, in tantivy's syntax: `"word" "a phrase"`.
+    pub fn conjunctive_query(&self) -> String {
+        self.words
+            .iter()
+            .chain(&self.phrases)
+            .map(|text| quote(text))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// All words as a phrase, along with the phrases, in tantivy's syntax. None when
+    /// there are no words.
+    pub fn phrase_query(&self) -> Option<String> {
+        if self.words.is_empty() {
+            return None;
+        }
+
+        let phrases = std::iter::once(quote(&self.text()))
+            .chain(self.phrases.iter().map(|phrase| quote(phrase)))
+            .collect::<Vec<_>>();
+
+        Some(phrases.join(" "))
+    }
+}
+
+/// Books to search in, as a list of source names: `book,nomicon`, or of the names of
+/// excluded ones: `-tokio`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BookFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl BookFilter {
+    pub fn parse(s: &str) -> Self {
+        let mut books = BookFilter::default();
+
+        for name in s.split(',').map(|name| name.trim().to_lowercase()) {
+            match name.strip_prefix('-') {
+                Some(excluded) if !excluded.is_empty() => books.exclude.push(excluded.to_string()),
+                Some(_) => {}
+                None if !name.is_empty() => books.include.push(name),
+                None => {}
+            }
+        }
+
+        books
+    }
+}
+
+/// Text as a phrase in tantivy's syntax, special characters don't need escaping in
+/// phrases.
+pub fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace(['"', '\\'], " ").trim())
+}
+
+/// Reads `name:` of a filter, if the next token starts with one.
+fn read_filter_name(q: &str, chars: &mut Peekable<CharIndices>) -> Option<String> {
+    let &(start, _) = chars.peek()?;
+
+    let name_len = q[start..]
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(q.len() - start);
+    let rest = &q[start + name_len..];
+
+    if name_len == 0 || !rest.starts_with(':') || rest.starts_with("::") {
+        return None;
+    }
+
+    let name = q[start..start + name_len].to_lowercase();
+    // Skips the name and the colon
+    for _ in 0..=name_len {
+        chars.next();
+    }
+
+    Some(name)
+}
+
+/// Reads a phrase in quotes or a word, empty at the end of the query or a whitespace.
+/// None for a phrase without the closing quote, its words are read as words then.
+fn read_value(
+    q: &str,
+    chars: &mut Peekable<CharIndices>,
+    ignored: &mut Vec<String>,
+) -> Option<String> {
+    let Some(&(start, c)) = chars.peek() else {
+        return Some(String::new());
+    };
+
+    if c == '"' {
+        chars.next();
+        return match q[start + 1..].find('"') {
+            Some(len) => {
+                while chars.next_if(|(i, _)| *i <= start + 1 + len).is_some() {}
+                Some(q[start + 1..start + 1 + len].trim().to_string())
+            }
+            None => {
+                ignored.push("\"".to_string());
+                None
+            }
+        };
+    }
+
+    let mut end = q.len();
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_whitespace() {
+            end = i;
+            break;
+        }
+        chars.next();
+    }
+
+    Some(q[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(field: FilterField, value: &str, negated: bool) -> Filter {
+        Filter {
+            field,
+            value: value.to_string(),
+            negated,
+        }
+    }
+
+    #[test]
+    fn test_words_phrases_and_exclusions() {
+        let query = UserQuery::parse(r#"borrow "mutable reference" -unsafe -"raw pointer""#);
+
+        assert_eq!(query.words, ["borrow"]);
+        assert_eq!(query.phrases, ["mutable reference"]);
+        assert_eq!(query.excluded, ["unsafe", "raw pointer"]);
+        assert!(query.ignored.is_empty());
+        assert_eq!(query.conjunctive_query(), r#""borrow" "mutable reference""#);
+        assert_eq!(
+            query.phrase_query().unwrap(),
+            r#""borrow" "mutable reference""#
+        );
+        assert_eq!(
+            UserQuery::parse(r#""mutable reference""#).phrase_query(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_filters() {
+        let query = UserQuery::parse(r#"title:"smart pointers" book:Nomicon -lang:toml code:Rc"#);
+
+        assert_eq!(
+            query.filters,
+            [
+                filter(FilterField::Title, "smart pointers", false),
+                filter(FilterField::Book, "nomicon", false),
+                filter(FilterField::Lang, "toml", true),
+            ]
+        );
+        assert!(query.code);
+        assert_eq!(query.words, ["Rc"]);
+        assert!(UserQuery::parse("lang:toml").code_only());
+    }
+
+    #[test]
+    fn test_rust_syntax_is_not_a_filter() {
+        let query = UserQuery::parse("std::fmt::Display impl Trait for Vec<T>");
+
+        assert_eq!(
+            query.words,
+            ["std::fmt::Display", "impl", "Trait", "for", "Vec<T>"]
+        );
+        assert!(query.filters.is_empty());
+        assert_eq!(
+            query.phrase_query().unwrap(),
+            r#""std::fmt::Display impl Trait for Vec<T>""#
+        );
+    }
+
+    #[test]
+    fn test_malformed_parts_are_ignored() {
+        let query = UserQuery::parse(r#"crate:tokio title: spawn "blocking task"#);
+
+        assert_eq!(query.words, ["tokio", "spawn", "blocking", "task"]);
+        assert_eq!(query.ignored, ["crate:", "title:", "\""]);
+
+        let query = UserQuery::parse(r#"fn parse() -> Result "" -"#);
+        assert_eq!(query.words, ["fn", "parse()", "->", "Result", "-"]);
+        assert_eq!(query.ignored, ["\"\""]);
+    }
+
+    #[test]
+    fn test_book_filter() {
+        let books = BookFilter::parse("Book, nomicon,-tokio,,-");
+
+        assert_eq!(books.include, ["book", "nomicon"]);
+        assert_eq!(books.exclude, ["tokio"]);
+
+        let query = UserQuery::parse("unsafe book:patterns").with_books(&books);
+        assert_eq!(query.filters(FilterField::Book).count(), 4);
+        assert!(query.without_books().filters.is_empty());
+    }
+}
@@ -1,13 +1,74 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
 
-use crate::index::{SearchIndex, SearchResult};
-use crate::{INDEX_CODE_DIR, INDEX_PAGE_DIR};
+use crate::autocomplete::{Autocomplete, QueryCounts};
+use crate::error::Error;
+use crate::generations::Generations;
+use crate::index::{BookCount, SearchIndex, SearchPage, SearchResult};
+use crate::query::{BookFilter, FilterField, UserQuery};
+use crate::spelling::Suggester;
+use crate::synonyms::Synonyms;
+use crate::{CODE_INDEX, PAGE_INDEX};
 use bitflags::bitflags;
+use tantivy::query::{AllQuery, BooleanQuery, Occur, Query};
+use tantivy::DocAddress;
 
 bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct SearchFlags: u32 {
         const DEFAULT   = 0b00000001;
         const CODE_ONLY = 0b00000010;
+        /// Search the suggested correction of a query without hits instead
+        const AUTOCORRECT = 0b00000100;
+        /// Return whole bodies of code blocks along with their snippets
+        const FULL_BODY   = 0b00001000;
+        /// Group the hits of a chapter, its best section with its code blocks
+        const GROUP       = 0b00010000;
+    }
+}
+
+/// Default number of results on a page
+pub const DEFAULT_LIMIT: usize = 20;
+
+/// Maximal number of results on a page
+pub const MAX_LIMIT: usize = 100;
+
+/// Number of lines of the snippets of code blocks
+pub const CODE_SNIPPET_LINES: usize = 8;
+
+/// Number of the best hits of every stage which are ranked, the results can't be paged
+/// deeper
+pub const MAX_RESULTS_WINDOW: usize = 1000;
+
+/// Constant of the reciprocal rank fusion, dampens the advantage of the top ranks.
+const RRF_K: f32 = 60.0;
+
+/// Stages of the search, from the most to the least precise one.
+#[derive(Debug, Clone, Copy)]
+enum Stage {
+    /// Full query as a phrase: '"impl trait"'
+    Phrase,
+    /// All words in any order: 'impl trait'
+    Conjunctive,
+    /// Typo tolerant search in titles
+    FuzzyTitle,
+    /// Typo tolerant search in bodies
+    FuzzyBody,
+    /// All words of a query rewritten by the synonyms: 'vec push' for 'vector push'
+    Expansion,
+}
+
+impl Stage {
+    /// Weight of the stage in the fusion, precise stages outweigh the loose ones.
+    fn weight(self) -> f32 {
+        match self {
+            Stage::Phrase => 1.0,
+            Stage::Conjunctive => 0.7,
+            Stage::FuzzyTitle => 0.4,
+            Stage::FuzzyBody => 0.3,
+            Stage::Expansion => 0.5,
+        }
     }
 }
 
@@ -16,106 +77,881 @@ pub struct Ranking {
     index_page: SearchIndex,
     /// Code blocks
     index_code: SearchIndex,
+    /// Rewrites of the queries
+    synonyms: Synonyms,
+    /// Titles of the books by the names of their sources
+    book_titles: BTreeMap<String, String>,
+    /// Corrections of misspelled queries
+    suggester: Suggester,
+    /// Completions of partially typed queries
+    autocomplete: Autocomplete,
+    /// Searches of the queries, the popular ones are completed first
+    searches: Arc<QueryCounts>,
+    /// Hits of every stage which are ranked
+    results_window: usize,
 }
 
-impl Default for Ranking {
-    fn default() -> Self {
-        Self::new()
+impl Ranking {
+    /// Opens the current generation of the indexes in the directory.
+    pub fn open(index_dir: &Path) -> Result<Self, Error> {
+        Self::open_generation(&Generations::new(index_dir).current_dir()?)
     }
-}
 
-impl Ranking {
-    pub fn new() -> Self {
-        let index_page = SearchIndex::open(INDEX_PAGE_DIR).unwrap();
-        let mut index_code = SearchIndex::open(INDEX_CODE_DIR).unwrap();
+    /// Opens the indexes of the generation in the directory.
+    pub fn open_generation(dir: &Path) -> Result<Self, Error> {
+        let index_page = SearchIndex::open(&dir.join(PAGE_INDEX).to_string_lossy())?;
+        let mut index_code = SearchIndex::open(&dir.join(CODE_INDEX).to_string_lossy())?;
 
-        // This index has code in the body, we want to return it, without snippet.
+        // This index has code in the body, its snippets are lines of the code.
         index_code.set_return_body();
         index_code.set_skip_snippet();
 
-        Self {
+        let mut book_titles = index_code.book_titles()?;
+        book_titles.extend(index_page.book_titles()?);
+
+        let suggester = Suggester::from_indexes(&[&index_page, &index_code])?;
+        let autocomplete = Autocomplete::from_indexes(&[&index_page, &index_code])?;
+
+        Ok(Self {
             index_page,
             index_code,
+            synonyms: Synonyms::default(),
+            book_titles,
+            suggester,
+            autocomplete,
+            searches: Arc::default(),
+            results_window: MAX_RESULTS_WINDOW,
+        })
+    }
+
+    /// Searches also the rewrites of the queries by the synonyms.
+    pub fn set_synonyms(&mut self, synonyms: Synonyms) {
+        self.synonyms = synonyms;
+    }
+
+    /// Counts the searches into the counts, ie. the ones shared with the previous
+    /// rankings of the indexes.
+    pub fn set_searches(&mut self, searches: Arc<QueryCounts>) {
+        self.searches = searches;
+    }
+
+    /// Ranks at most `window` hits of every stage instead of `MAX_RESULTS_WINDOW`.
+    pub fn set_results_window(&mut self, window: usize) {
+        self.results_window = window;
+    }
+
+    /// Number of documents in the page and code indexes.
+    pub fn num_docs(&self) -> (u64, u64) {
+        (self.index_page.num_docs(), self.index_code.num_docs())
+    }
+
+    /// Searches the indexes and returns `limit` results starting at `offset`.
+    ///
+    /// The query is parsed as a `UserQuery`, its filters and exclusions apply to every
+    /// stage. Every stage fetches its `MAX_RESULTS_WINDOW` best hits, whatever page is
+    /// asked for, so the fused order is the same on every page. `total` is the number
+    /// of the fused hits, `truncated` is set when a stage matched more documents than
+    /// that, they can't be paged to. Rewrites of the query by the synonyms are searched
+    /// as an extra stage, weighted below the original query.
+    ///
+    /// Parts of the query which can't be parsed are left out and reported in
+    /// `ignored`. Fails when an index can't be searched.
+    ///
+    /// The search is restricted to the `books`, along with the `book:` filters of the
+    /// query. The documents matching in every book are counted regardless of them.
+    ///
+    /// A correction of the misspelled words is suggested, with `AUTOCORRECT` it's
+    /// searched instead of a query without hits.
+    ///
+    /// With `GROUP`, the results are chapters with their matching code blocks, and
+    /// `offset`, `limit` and `total` count the chapters of the fused hits.
+    pub fn search(
+        &self,
+        q: &str,
+        books: &BookFilter,
+        flags: SearchFlags,
+        offset: usize,
+        limit: usize,
+    ) -> Result<SearchPage, Error> {
+        let suggestion = self.suggester.suggest(q);
+        let query = UserQuery::parse(q).with_books(books);
+        let mut page = self.search_query(query, flags, offset, limit)?;
+
+        if let Some(suggestion) = &suggestion {
+            if page.total == 0 && flags.contains(SearchFlags::AUTOCORRECT) {
+                let query = UserQuery::parse(suggestion).with_books(books);
+                page = SearchPage {
+                    autocorrected: true,
+                    ..self.search_query(query, flags, offset, limit)?
+                };
+            }
+        }
+
+        // Searches of the first pages with results, as they were typed
+        if offset == 0 && page.total > 0 && !page.autocorrected {
+            self.searches.record(q);
+        }
+
+        Ok(SearchPage { suggestion, ..page })
+    }
+
+    /// Book filters with the names of the sources as they are configured, the filters
+    /// are lowercased. Unknown names are kept, they match nothing.
+    fn resolve_books(&self, mut query: UserQuery) -> UserQuery {
+        for filter in &mut query.filters {
+            if filter.field != FilterField::Book {
+                continue;
+            }
+            if let Some(name) = self
+                .book_titles
+                .keys()
+                .find(|name| name.to_lowercase() == filter.value.to_lowercase())
+            {
+                filter.value = name.clone();
+            }
         }
+
+        query
     }
 
-    pub fn search(&self, q: &str, flags: SearchFlags) -> Vec<SearchResult> {
-        let mut urls = HashSet::<String>::new();
-        let mut results = Vec::<SearchResult>::new();
-        let mut prev_len: usize;
+    fn search_query(
+        &self,
+        query: UserQuery,
+        flags: SearchFlags,
+        offset: usize,
+        limit: usize,
+    ) -> Result<SearchPage, Error> {
+        let query = self.resolve_books(query);
+        let all_books = query.without_books();
+        let mut ignored = query.ignored.clone();
 
-        let indexes = match flags.contains(SearchFlags::CODE_ONLY) {
+        if query.is_empty() {
+            return Ok(SearchPage {
+                ignored,
+                ..Default::default()
+            });
+        }
+
+        let indexes = match flags.contains(SearchFlags::CODE_ONLY) || query.code_only() {
             true => vec![&self.index_code],
             false => vec![&self.index_page, &self.index_code],
         };
 
-        // 1/3 search full query '"impl trait"'
-        let all_words_q = format!("\"{}\"", q);
+        let limit = limit.min(MAX_LIMIT);
+        let group = flags.contains(SearchFlags::GROUP);
 
-        for index in &indexes {
-            if let Ok(res) = index.search(&all_words_q) {
-                results.extend(res);
-            }
+        let text = query.text();
+        let phrases = UserQuery {
+            phrases: query.phrases.clone(),
+            ..Default::default()
         }
+        .conjunctive_query();
+
+        // Rewrites keep the phrases of the query
+        let expansions = self.synonyms.expand(&text);
+        let expansion_queries: Vec<String> = expansions
+            .iter()
+            .map(|expansion| {
+                let expansion = UserQuery::parse(expansion).conjunctive_query();
+                format!("{} {}", expansion, phrases)
+            })
+            .collect();
+
+        let mut ranked = Vec::new();
+        let mut truncated = false;
+        let mut unions = Vec::new();
+        let mut book_counts = BTreeMap::<String, usize>::new();
+
+        for (index_no, index) in indexes.iter().enumerate() {
+            let (filters, errors) = index.filter_clauses(&query)?;
+            ignored.extend(errors);
+            let (all_books_filters, _) = index.filter_clauses(&all_books)?;
 
-        results.iter().for_each(|r| {
-            urls.insert(r.url.clone());
-        });
-        prev_len = results.len();
-
-        // 2/3 if no results, search 'impl trait'
-        for index in &indexes {
-            if let Ok(res) = index.search(q) {
-                let res: Vec<SearchResult> =
-                    res.into_iter().filter(|r| urls.contains(&r.url)).collect();
-                results.extend(res);
+            let (conjunctive, errors) = match query.words.is_empty() && query.phrases.is_empty() {
+                // Only filters: 'book:nomicon'
+                true => (Box::new(AllQuery) as Box<dyn Query>, vec![]),
+                false => index.parse_query_lenient(&query.conjunctive_query()),
+            };
+            ignored.extend(errors);
+
+            // Phrase query '"impl trait"'
+            let mut stages = vec![];
+            if let Some(phrase) = query.phrase_query() {
+                stages.push((Stage::Phrase, index.parse_query_lenient(&phrase).0));
+            }
+            stages.push((Stage::Conjunctive, conjunctive));
+            if !text.is_empty() {
+                stages.push((Stage::FuzzyTitle, index.fuzzy_query_title(&text)?));
+                stages.push((Stage::FuzzyBody, index.fuzzy_query_body(&text)?));
+            }
+            for expansion in &expansion_queries {
+                stages.push((Stage::Expansion, index.parse_query_lenient(expansion).0));
+            }
+
+            let mut queries = Vec::new();
+            let mut all_books_queries = Vec::new();
+
+            for (stage, stage_query) in stages {
+                all_books_queries.push(filtered(stage_query.box_clone(), &all_books_filters));
+
+                let stage_query = filtered(stage_query, &filters);
+                let (docs, count) = index.top_docs(stage_query.as_ref(), 0, self.results_window)?;
+                truncated |= count > docs.len();
+                ranked.push((stage, index_no, docs));
+                queries.push(stage_query);
+            }
+
+            // Snippets are of the terms of every stage
+            unions.push(BooleanQuery::union(queries));
+
+            for (name, count) in index.count_by_source(&BooleanQuery::union(all_books_queries))? {
+                *book_counts.entry(name).or_default() += count;
             }
         }
 
-        results.iter().skip(prev_len).for_each(|r| {
-            urls.insert(r.url.clone());
-        });
+        let mut books: Vec<BookCount> = book_counts
+            .into_iter()
+            .map(|(name, count)| BookCount {
+                title: self.book_titles.get(&name).cloned().unwrap_or_default(),
+                name,
+                count,
+            })
+            .collect();
+        books.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+        let words: Vec<&String> = query.words.iter().chain(&query.phrases).collect();
+        let finish = |index_no: usize, mut result: SearchResult| -> Result<SearchResult, Error> {
+            indexes[index_no].highlight(&mut result, &words)?;
+            if result.body.is_some() {
+                result.set_code_snippet(CODE_SNIPPET_LINES);
+                if !flags.contains(SearchFlags::FULL_BODY) {
+                    result.body = None;
+                    result.highlights.body.clear();
+                }
+            }
+            Ok(result)
+        };
 
-        // 3/3 if no results, fuzzy search in title and then in body
-        if results.is_empty() {
-            prev_len = results.len();
+        // Loads the hits, in their order
+        let load = |hits: &[Hit]| -> Result<Vec<SearchResult>, Error> {
+            let mut results: Vec<Option<SearchResult>> = hits.iter().map(|_| None).collect();
 
-            for index in &indexes {
-                if let Ok(res) = index.fuzzy_search_title(q) {
-                    let res: Vec<SearchResult> =
-                        res.into_iter().filter(|r| urls.contains(&r.url)).collect();
-                    results.extend(res);
+            for (index_no, index) in indexes.iter().enumerate() {
+                let (positions, docs): (Vec<usize>, Vec<(f32, DocAddress)>) = hits
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, hit)| hit.index_no == index_no)
+                    .map(|(position, hit)| (position, (hit.score, hit.doc_address)))
+                    .unzip();
+
+                for (position, result) in positions
+                    .into_iter()
+                    .zip(index.results(&unions[index_no], &docs)?)
+                {
+                    results[position] = Some(finish(index_no, result)?);
                 }
             }
 
-            results.iter().skip(prev_len).for_each(|r| {
-                urls.insert(r.url.clone());
-            });
+            Ok(results.into_iter().flatten().collect())
+        };
 
-            for index in &indexes {
-                if let Ok(res) = index.fuzzy_search_body(q) {
-                    let res: Vec<SearchResult> =
-                        res.into_iter().filter(|r| urls.contains(&r.url)).collect();
-                    results.extend(res);
+        let hits = fuse(ranked);
+        let total;
+        let mut results = Vec::new();
+
+        if group {
+            let mut chapters = vec![String::new(); hits.len()];
+            for (index_no, index) in indexes.iter().enumerate() {
+                let (positions, docs): (Vec<usize>, Vec<DocAddress>) = hits
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, hit)| hit.index_no == index_no)
+                    .map(|(position, hit)| (position, hit.doc_address))
+                    .unzip();
+
+                for (position, url) in positions.into_iter().zip(index.chapter_urls(&docs)?) {
+                    chapters[position] = url;
                 }
             }
+
+            // The code index is the last one
+            let is_code = |index_no| index_no == indexes.len() - 1;
+            let groups = group_by_chapter(hits.into_iter().zip(chapters), is_code);
+            total = groups.len();
+
+            let groups: Vec<_> = groups.into_iter().skip(offset).take(limit).collect();
+            let group_hits: Vec<Hit> = groups
+                .iter()
+                .flat_map(|(head, code_blocks)| std::iter::once(head).chain(code_blocks))
+                .copied()
+                .collect();
+            let mut loaded = load(&group_hits)?.into_iter();
+
+            for (_, code_blocks) in groups {
+                let Some(mut result) = loaded.next() else {
+                    break;
+                };
+                result.code_blocks = loaded.by_ref().take(code_blocks.len()).collect();
+                results.push(result);
+            }
+        } else {
+            total = hits.len();
+            let page: Vec<Hit> = hits.into_iter().skip(offset).take(limit).collect();
+            results = load(&page)?;
         }
 
-        for tok in q.split(' ') {
-            let highligthed = format!("<b>{}</b>", tok);
-            results.iter_mut().for_each(|r| {
-                if r.body.is_some() {
-                    r.body = Some(
-                        String::from(r.body.as_ref().unwrap()).replace(tok, highligthed.as_str()),
-                    )
-                }
-            });
+        let mut unique = HashSet::new();
+        ignored.retain(|part| unique.insert(part.clone()));
+
+        Ok(SearchPage {
+            results,
+            total,
+            truncated,
+            expansions,
+            ignored,
+            books,
+            ..Default::default()
+        })
+    }
+
+    /// At most `limit` completions of the partially typed query.
+    pub fn autocomplete(&self, q: &str, limit: usize) -> Vec<String> {
+        self.autocomplete.complete(q, limit, &self.searches)
+    }
+}
+
+/// The query restricted by the filter clauses.
+fn filtered(query: Box<dyn Query>, filters: &[(Occur, Box<dyn Query>)]) -> Box<dyn Query> {
+    if filters.is_empty() {
+        return query;
+    }
+
+    let clauses = std::iter::once((Occur::Must, query))
+        .chain(
+            filters
+                .iter()
+                .map(|(occur, filter)| (*occur, filter.box_clone())),
+        )
+        .collect();
+
+    Box::new(BooleanQuery::new(clauses))
+}
+
+/// Scores and addresses of the best hits of a stage in the index with the number
+type StageHits = (Stage, usize, Vec<(f32, DocAddress)>);
+
+/// A hit of the fused stages, ranked before it's loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Hit {
+    /// Number of the index of the document
+    index_no: usize,
+    doc_address: DocAddress,
+    /// Tantivy score of the most precise stage the document was found in
+    score: f32,
+}
+
+/// Hits grouped by their chapters, in the order of their best hits. The head of a group
+/// is the best section of the chapter, or its best code block when no section matched,
+/// followed by the matching code blocks. Other sections of the chapter are left out.
+fn group_by_chapter(
+    hits: impl IntoIterator<Item = (Hit, String)>,
+    is_code: impl Fn(usize) -> bool,
+) -> Vec<(Hit, Vec<Hit>)> {
+    let mut groups: Vec<(Hit, Vec<Hit>)> = Vec::new();
+    let mut positions = HashMap::<String, usize>::new();
+
+    for (hit, chapter) in hits {
+        let Some(&position) = positions.get(&chapter) else {
+            positions.insert(chapter, groups.len());
+            groups.push((hit, vec![]));
+            continue;
+        };
+
+        let (head, code_blocks) = &mut groups[position];
+        match (is_code(head.index_no), is_code(hit.index_no)) {
+            // The section takes the place of the code block, which ranks above the others
+            (true, false) => code_blocks.insert(0, std::mem::replace(head, hit)),
+            (_, true) => code_blocks.push(hit),
+            (false, false) => {}
         }
+    }
 
-        results
+    groups
+}
+
+/// Weighted reciprocal rank fusion of the per stage and per index lists of hits.
+///
+/// Hits are deduped by document, that is by its address within its index. A hit keeps
+/// the tantivy score of the most precise stage it was found in. Ties are broken by
+/// that score, then by index and address, so the order doesn't depend on the order of
+/// the input lists.
+fn fuse(ranked: Vec<StageHits>) -> Vec<Hit> {
+    let mut fused = HashMap::<(usize, DocAddress), (f32, f32)>::new();
+
+    for (stage, index_no, docs) in ranked {
+        for (rank, (score, doc_address)) in docs.into_iter().enumerate() {
+            let rrf = stage.weight() / (RRF_K + rank as f32 + 1.0);
+
+            fused
+                .entry((index_no, doc_address))
+                .and_modify(|(fused_score, _)| *fused_score += rrf)
+                .or_insert((rrf, score));
+        }
+    }
+
+    let mut fused: Vec<_> = fused.into_iter().collect();
+
+    fused.sort_by(|(a_key, a), (b_key, b)| {
+        b.0.total_cmp(&a.0)
+            .then_with(|| b.1.total_cmp(&a.1))
+            .then_with(|| a_key.cmp(b_key))
+    });
+
+    fused
+        .into_iter()
+        .map(|((index_no, doc_address), (_, score))| Hit {
+            index_no,
+            doc_address,
+            score,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::index::chapter_url;
+    use crate::parsers::CodeBlock;
+    use crate::test_utils::{document, index_sources, rust, TestSource};
+
+    fn hit(doc: u32, score: f32) -> (f32, DocAddress) {
+        (score, DocAddress::new(0, doc))
+    }
+
+    fn urls(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|r| r.url.as_str()).collect()
+    }
+
+    /// Numbers of the indexes and the documents of the hits.
+    fn fused_docs(hits: &[Hit]) -> Vec<(usize, u32)> {
+        hits.iter()
+            .map(|hit| (hit.index_no, hit.doc_address.doc_id))
+            .collect()
+    }
+
+    #[test]
+    fn test_fuse_dedupes_and_ranks() {
+        let hits = fuse(vec![
+            (Stage::Phrase, 0, vec![hit(0, 3.0), hit(1, 2.0)]),
+            (
+                Stage::Conjunctive,
+                0,
+                vec![hit(1, 5.0), hit(0, 4.0), hit(2, 1.0)],
+            ),
+        ]);
+
+        assert_eq!(fused_docs(&hits), [(0, 0), (0, 1), (0, 2)]);
+        // The score of the phrase stage is kept
+        assert_eq!(hits[0].score, 3.0);
+    }
+
+    #[test]
+    fn test_fuse_later_stages_add_recall() {
+        let hits = fuse(vec![
+            (Stage::Phrase, 0, vec![]),
+            (Stage::Conjunctive, 0, vec![hit(0, 1.0)]),
+            (Stage::FuzzyTitle, 0, vec![hit(1, 1.0)]),
+            (Stage::FuzzyBody, 1, vec![hit(1, 1.0)]),
+        ]);
+
+        assert_eq!(fused_docs(&hits), [(0, 0), (0, 1), (1, 1)]);
     }
 
-    // fn autocomplete(q: &str) {
-    //     todo!();
-    // }
+    #[test]
+    fn test_fuse_keeps_code_blocks_of_one_url() {
+        let hits = fuse(vec![(
+            Stage::Conjunctive,
+            1,
+            vec![hit(0, 1.0), hit(1, 1.0)],
+        )]);
+
+        assert_eq!(hits.len(), 2);
+    }
+
+    #[test]
+    fn test_group_by_chapter() {
+        let hit = |index_no, doc, chapter: &str| {
+            let (score, doc_address) = hit(doc, 1.0);
+            let hit = Hit {
+                index_no,
+                doc_address,
+                score,
+            };
+            (hit, chapter.to_string())
+        };
+
+        let groups = group_by_chapter(
+            vec![
+                hit(1, 0, "ch1.html"),
+                hit(0, 0, "ch2.html"),
+                hit(0, 1, "ch1.html"),
+                hit(1, 1, "ch2.html"),
+                hit(0, 2, "ch1.html"),
+                hit(1, 2, "ch1.html"),
+            ],
+            |index_no| index_no == 1,
+        );
+
+        let groups: Vec<_> = groups
+            .iter()
+            .map(|(head, code_blocks)| {
+                let code_blocks: Vec<_> = code_blocks
+                    .iter()
+                    .map(|hit| hit.doc_address.doc_id)
+                    .collect();
+                ((head.index_no, head.doc_address.doc_id), code_blocks)
+            })
+            .collect();
+        assert_eq!(groups, [((0, 1), vec![0, 2]), ((0, 0), vec![1])]);
+    }
+
+    #[test]
+    fn test_fuse_is_deterministic() {
+        let a = fuse(vec![
+            (Stage::Conjunctive, 0, vec![hit(1, 1.0)]),
+            (Stage::Conjunctive, 1, vec![hit(0, 1.0)]),
+        ]);
+        let b = fuse(vec![
+            (Stage::Conjunctive, 1, vec![hit(0, 1.0)]),
+            (Stage::Conjunctive, 0, vec![hit(1, 1.0)]),
+        ]);
+
+        assert_eq!(fused_docs(&a), fused_docs(&b));
+    }
+
+    /// Indexes two books into a temporary directory.
+    fn test_ranking() -> (TempDir, Ranking) {
+        let dir = tempfile::tempdir().unwrap();
+
+        let book = TestSource {
+            title: "The Book",
+            documents: vec![
+                document(
+                    "book/ownership",
+                    "Ownership",
+                    "Every value has an owner, references borrow the value",
+                    rust(&["let s = String::from(\"hello\");"]),
+                ),
+                document(
+                    "book/cargo",
+                    "Cargo",
+                    "Dependencies of the package are listed in the manifest",
+                    vec![CodeBlock {
+                        lang: "toml".to_string(),
+                        edition: None,
+                        code: "[dependencies]\nrand = \"0.8\"".to_string(),
+                    }],
+                ),
+            ],
+            ..Default::default()
+        };
+        let nomicon = TestSource {
+            title: "The Rustonomicon",
+            documents: vec![document(
+                "nomicon/references",
+                "References",
+                "A reference must not outlive its referent, unsafe code can borrow anything",
+                vec![],
+            )],
+            ..Default::default()
+        };
+
+        // Capitalized, the book filters match the names whatever their case
+        index_sources(dir.path(), &[("book", &book), ("Nomicon", &nomicon)]);
+        let ranking = Ranking::open(dir.path()).unwrap();
+
+        (dir, ranking)
+    }
+
+    #[test]
+    fn test_query_language() {
+        let (_dir, ranking) = test_ranking();
+        let search = |q| {
+            ranking
+                .search(
+                    q,
+                    &BookFilter::default(),
+                    SearchFlags::DEFAULT,
+                    0,
+                    DEFAULT_LIMIT,
+                )
+                .unwrap()
+        };
+
+        assert_eq!(
+            urls(&search("borrow").results),
+            ["book/ownership", "nomicon/references"]
+        );
+        assert_eq!(
+            urls(&search("borrow book:nomicon").results),
+            ["nomicon/references"]
+        );
+        assert_eq!(
+            urls(&search("borrow book:NOMICON").results),
+            ["nomicon/references"]
+        );
+        assert_eq!(urls(&search("borrow -unsafe").results), ["book/ownership"]);
+        // The page and its code block
+        assert_eq!(
+            urls(&search("title:ownership").results),
+            ["book/ownership", "book/ownership"]
+        );
+
+        let page = search("lang:toml dependencies");
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.results[0].lang.as_deref(), Some("toml"));
+
+        let page = search("\"borrow the value\" crate:owner");
+        assert_eq!(urls(&page.results), ["book/ownership"]);
+        assert_eq!(page.ignored, ["crate:"]);
+    }
+
+    #[test]
+    fn test_book_counts() {
+        let (_dir, ranking) = test_ranking();
+        let counts = |page: &SearchPage| {
+            page.books
+                .iter()
+                .map(|book| (book.title.clone(), book.count))
+                .collect::<Vec<_>>()
+        };
+
+        let books = BookFilter::parse("-nomicon");
+        let page = ranking
+            .search("borrow", &books, SearchFlags::DEFAULT, 0, DEFAULT_LIMIT)
+            .unwrap();
+        assert_eq!(urls(&page.results), ["book/ownership"]);
+        assert_eq!(page.total, 1);
+        // Counted in every book, so the filter can be changed, the most matching first, then
+        // by source name
+        assert_eq!(
+            counts(&page),
+            [
+                ("The Rustonomicon".to_string(), 1),
+                ("The Book".to_string(), 1)
+            ]
+        );
+
+        let page = ranking
+            .search(
+                "value",
+                &BookFilter::default(),
+                SearchFlags::DEFAULT,
+                0,
+                DEFAULT_LIMIT,
+            )
+            .unwrap();
+        assert_eq!(counts(&page), [("The Book".to_string(), 1)]);
+    }
+
+    #[test]
+    fn test_highlights() {
+        let (_dir, ranking) = test_ranking();
+
+        let page = ranking
+            .search(
+                "ownership borrow",
+                &BookFilter::default(),
+                SearchFlags::DEFAULT,
+                0,
+                DEFAULT_LIMIT,
+            )
+            .unwrap();
+        let result = &page.results[0];
+        assert_eq!(result.url, "book/ownership");
+        assert_eq!(result.highlights.title.first(), Some(&(0..9)));
+
+        let snippet = result.snippet.as_deref().unwrap();
+        let highlighted: Vec<_> = result
+            .highlights
+            .snippet
+            .iter()
+            .map(|range| &snippet[range.clone()])
+            .collect();
+        assert_eq!(highlighted, ["borrow"]);
+
+        let html = page.results.into_iter().next().unwrap().into_html();
+        assert_eq!(html.title, "<b>Ownership</b>");
+        assert!(html.highlights.title.is_empty());
+
+        // Code blocks are returned as snippets
+        let search = |flags| {
+            ranking
+                .search("hello", &BookFilter::default(), flags, 0, DEFAULT_LIMIT)
+                .unwrap()
+                .results
+        };
+        let code = &search(SearchFlags::CODE_ONLY)[0];
+        assert_eq!(code.body, None);
+        assert_eq!(
+            code.snippet.as_deref(),
+            Some("let s = String::from(\"hello\");")
+        );
+        assert_eq!(code.snippet_lines.map(|lines| lines.start), Some(1));
+        let code = &search(SearchFlags::CODE_ONLY | SearchFlags::FULL_BODY)[0];
+        assert!(code.body.is_some());
+    }
+
+    #[test]
+    fn test_grouped_results() {
+        let (_dir, ranking) = test_ranking();
+        let search = |q, flags| {
+            ranking
+                .search(q, &BookFilter::default(), flags, 0, DEFAULT_LIMIT)
+                .unwrap()
+        };
+
+        let page = search("title:ownership", SearchFlags::DEFAULT);
+        assert_eq!(page.total, 2);
+
+        let page = search("title:ownership", SearchFlags::GROUP);
+        assert_eq!(page.total, 1);
+        assert_eq!(urls(&page.results), ["book/ownership"]);
+        assert_eq!(page.results[0].lang, None);
+        assert_eq!(urls(&page.results[0].code_blocks), ["book/ownership"]);
+
+        // Pages count chapters
+        let page = ranking
+            .search("borrow", &BookFilter::default(), SearchFlags::GROUP, 1, 1)
+            .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(urls(&page.results), ["nomicon/references"]);
+    }
+
+    /// Indexes a guide with a chapter of many sections matching `borrow`, and chapters
+    /// of one section, into a temporary directory.
+    fn guide_ranking() -> (TempDir, Ranking) {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut documents: Vec<_> = (0..6)
+            .map(|section| {
+                let url = format!("guide/borrowing#s{}", section);
+                document(&url, "Borrowing", "borrow borrow", vec![])
+            })
+            .collect();
+        for chapter in ["moves", "slices", "traits"] {
+            let url = format!("guide/{}", chapter);
+            let body = "Values can be moved, sliced or borrow their parts";
+            documents.push(document(&url, chapter, body, vec![]));
+        }
+        let guide = TestSource {
+            title: "Guide",
+            documents,
+            ..Default::default()
+        };
+        index_sources(dir.path(), &[("guide", &guide)]);
+        let ranking = Ranking::open(dir.path()).unwrap();
+
+        (dir, ranking)
+    }
+
+    #[test]
+    fn test_stable_pages() {
+        let (_dir, mut ranking) = guide_ranking();
+        let search = |ranking: &Ranking, offset, limit| {
+            ranking
+                .search(
+                    "borrow",
+                    &BookFilter::default(),
+                    SearchFlags::DEFAULT,
+                    offset,
+                    limit,
+                )
+                .unwrap()
+        };
+
+        let page = search(&ranking, 0, DEFAULT_LIMIT);
+        assert_eq!(page.total, 9);
+        assert!(!page.truncated);
+
+        // Pages are cut from the same order
+        let all = urls(&page.results);
+        let paged: Vec<_> = (0..3)
+            .flat_map(|page| search(&ranking, page * 3, 3).results)
+            .collect();
+        assert_eq!(urls(&paged), all);
+
+        // Hits beyond the window aren't counted
+        ranking.set_results_window(4);
+        let page = search(&ranking, 0, DEFAULT_LIMIT);
+        assert!(page.truncated);
+        assert_eq!(page.total, page.results.len());
+        assert!(search(&ranking, page.total, 1).results.is_empty());
+    }
+
+    #[test]
+    fn test_grouped_pages() {
+        // One chapter with more matching sections than the window
+        let (_dir, mut ranking) = guide_ranking();
+
+        let chapters = |ranking: &Ranking, offset, limit| {
+            let page = ranking
+                .search(
+                    "borrow",
+                    &BookFilter::default(),
+                    SearchFlags::GROUP,
+                    offset,
+                    limit,
+                )
+                .unwrap();
+            let chapters: Vec<_> = page
+                .results
+                .iter()
+                .map(|result| chapter_url(&result.url).to_string())
+                .collect();
+            (page.total, chapters)
+        };
+
+        let (total, all) = chapters(&ranking, 0, DEFAULT_LIMIT);
+        assert_eq!(total, 4);
+        assert_eq!(all[0], "guide/borrowing");
+
+        // Pages neither repeat nor drop chapters
+        let paged: Vec<_> = (0..total)
+            .flat_map(|offset| {
+                let (page_total, page) = chapters(&ranking, offset, 1);
+                assert_eq!(page_total, total);
+                page
+            })
+            .collect();
+        assert_eq!(paged, all);
+
+        // Chapters beyond the window aren't counted
+        ranking.set_results_window(4);
+        let (total, all) = chapters(&ranking, 0, DEFAULT_LIMIT);
+        assert_eq!(total, all.len());
+        assert_eq!(chapters(&ranking, total, 1).1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_spelling_suggestion() {
+        let (_dir, ranking) = test_ranking();
+        let search = |q, flags| {
+            ranking
+                .search(q, &BookFilter::default(), flags, 0, DEFAULT_LIMIT)
+                .unwrap()
+        };
+
+        let page = search("ownrship book:book", SearchFlags::DEFAULT);
+        assert_eq!(page.suggestion.as_deref(), Some("ownership book:book"));
+        // Found by the fuzzy stages, there is nothing to correct
+        let page = search("ownrship book:book", SearchFlags::AUTOCORRECT);
+        assert!(!page.autocorrected);
+        assert!(page.total > 0);
+
+        let page = search("ownership referent", SearchFlags::DEFAULT);
+        assert_eq!(page.suggestion, None);
+    }
 }
@@ -0,0 +1,428 @@
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{FromRequestParts, Query, State};
+use axum::http::request::Parts;
+use axum::http::HeaderMap;
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{http::StatusCode, response::IntoResponse, Json, Router};
+use rust_indexed::autocomplete::{QueryCounts, DEFAULT_COMPLETIONS, MAX_COMPLETIONS};
+use rust_indexed::error::Error;
+use rust_indexed::generations::Generations;
+use rust_indexed::index::{BookCount, SearchPage, SearchResult};
+use rust_indexed::query::BookFilter;
+use rust_indexed::ranking::{Ranking, SearchFlags, DEFAULT_LIMIT, MAX_LIMIT};
+use rust_indexed::synonyms::Synonyms;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::thread::sleep;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task;
+
+struct AppState {
+    /// Searches hold their own reference, so a reload doesn't wait for them
+    ranking: RwLock<Arc<Ranking>>,
+    /// Searches of the queries, kept when the indexes are reloaded
+    searches: Arc<QueryCounts>,
+    index_dir: PathBuf,
+    synonyms: PathBuf,
+    /// Token of the admin endpoints, they are disabled without it
+    admin_token: Option<String>,
+}
+
+/// Serves the API. The current generation of the indexes and the synonyms are
+/// reloaded on SIGHUP and on `POST /admin/reload`.
+pub async fn serve(
+    listen: &str,
+    index_dir: &Path,
+    synonyms: &Path,
+    admin_token: Option<String>,
+) -> std::io::Result<()> {
+    // initialize tracing
+    // tracing_subscriber::fmt::init();
+
+    let searches = Arc::new(QueryCounts::default());
+    let (_, ranking) =
+        open_ranking(index_dir, synonyms, &searches).map_err(std::io::Error::other)?;
+
+    let app_state = Arc::new(AppState {
+        ranking: RwLock::new(Arc::new(ranking)),
+        searches,
+        index_dir: index_dir.to_path_buf(),
+        synonyms: synonyms.to_path_buf(),
+        admin_token,
+    });
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    let state = app_state.clone();
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match reload(&state).await {
+                Ok(response) => println!("Reloaded generation {:?}", response.generation),
+                Err(err) => eprintln!("Failed to reload the indexes: {}", err),
+            }
+        }
+    });
+
+    let app = Router::new()
+        .route("/search/", get(search)) // API
+        .route("/suggest/", get(suggest))
+        .route("/admin/reload", post(admin_reload))
+        .with_state(app_state);
+
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+
+    axum::serve(listener, app).await
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Opens the current generation of the indexes and records it as served, returns its
+/// name with the ranking.
+fn open_ranking(
+    index_dir: &Path,
+    synonyms: &Path,
+    searches: &Arc<QueryCounts>,
+) -> Result<(Option<String>, Ranking), BoxError> {
+    let generations = Generations::new(index_dir);
+    let generation = generations.current()?;
+
+    let mut ranking = Ranking::open_generation(&generations.generation_dir(generation.as_deref()))?;
+    ranking.set_synonyms(Synonyms::load(synonyms)?);
+    ranking.set_searches(searches.clone());
+
+    if let Some(name) = &generation {
+        generations.set_serving(name)?;
+    }
+
+    Ok((generation, ranking))
+}
+
+/// Opens the current generation of the indexes and swaps it in.
+async fn reload(state: &AppState) -> Result<ReloadResponse, String> {
+    let index_dir = state.index_dir.clone();
+    let synonyms = state.synonyms.clone();
+    let searches = state.searches.clone();
+
+    let (generation, ranking) =
+        task::spawn_blocking(move || open_ranking(&index_dir, &synonyms, &searches))
+            .await
+            .map_err(|err| err.to_string())?
+            .map_err(|err| err.to_string())?;
+
+    let (page_documents, code_documents) = ranking.num_docs();
+
+    *state.ranking.write().unwrap() = Arc::new(ranking);
+
+    Ok(ReloadResponse {
+        generation,
+        page_documents,
+        code_documents,
+    })
+}
+
+async fn admin_reload(
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ReloadResponse>, ApiError> {
+    let authorized = state.admin_token.as_ref().is_some_and(|token| {
+        headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            == Some(token.as_str())
+    });
+
+    if !authorized {
+        return Err(ApiError::Forbidden);
+    }
+
+    let response = reload(&state).await.map_err(ApiError::Reload)?;
+    println!("Reloaded generation {:?}", response.generation);
+
+    Ok(Json(response))
+}
+
+async fn search(
+    ApiQuery(params): ApiQuery<Params>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SearchResponse>, ApiError> {
+    let q_debug = params.q.clone();
+    let mut search_flags = SearchFlags::DEFAULT;
+
+    let q = match params.q.starts_with("code ") {
+        false => params.q,
+        true => {
+            search_flags |= SearchFlags::CODE_ONLY;
+            params.q[5..].to_string()
+        }
+    };
+    if params.autocorrect {
+        search_flags |= SearchFlags::AUTOCORRECT;
+    }
+    if params.full_body {
+        search_flags |= SearchFlags::FULL_BODY;
+    }
+    if params.group {
+        search_flags |= SearchFlags::GROUP;
+    }
+
+    let page = params.page.unwrap_or(1).max(1) as usize;
+    let per_page = params
+        .per_page
+        .map_or(DEFAULT_LIMIT, |per_page| per_page as usize)
+        .clamp(1, MAX_LIMIT);
+    let offset = (page - 1) * per_page;
+    let books = BookFilter::parse(&params.books);
+
+    let start = Instant::now();
+
+    let ranking = state.ranking.read().unwrap().clone();
+
+    let SearchPage {
+        results,
+        total,
+        truncated,
+        expansions,
+        ignored,
+        books,
+        suggestion,
+        autocorrected,
+    } = task::spawn_blocking(move || {
+        sleep(Duration::from_millis(200));
+        ranking.search(&q, &books, search_flags, offset, per_page)
+    })
+    .await
+    .map_err(|err| ApiError::Search(Error::Io(std::io::Error::other(err))))?
+    .map_err(ApiError::Search)?;
+
+    let duration = start.elapsed();
+
+    println!(
+        "{} results. duration = {:?} query = `{}` page = {}",
+        total, duration, q_debug, page
+    );
+
+    let results = match params.format {
+        Format::Text => results,
+        Format::Html => results.into_iter().map(SearchResult::into_html).collect(),
+    };
+
+    Ok(Json(SearchResponse {
+        results,
+        total,
+        truncated,
+        page,
+        per_page,
+        duration_milis: duration.as_millis(),
+        expansions: params.debug.then_some(expansions),
+        ignored,
+        books,
+        suggestion,
+        autocorrected,
+    }))
+}
+
+/// Completions of a partially typed query, they're cheap enough to answer in place.
+async fn suggest(
+    ApiQuery(params): ApiQuery<SuggestParams>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<SuggestResponse>, ApiError> {
+    let limit = params
+        .limit
+        .map_or(DEFAULT_COMPLETIONS, |limit| limit as usize)
+        .clamp(1, MAX_COMPLETIONS);
+
+    let ranking = state.ranking.read().unwrap().clone();
+
+    Ok(Json(SuggestResponse {
+        suggestions: ranking.autocomplete(&params.q, limit),
+    }))
+}
+
+/// Query string extractor, which answers the invalid parameters with an `ApiError`.
+struct ApiQuery<T>(T);
+
+impl<T: DeserializeOwned, S: Send + Sync> FromRequestParts<S> for ApiQuery<T> {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(params) = Query::from_request_parts(parts, state)
+            .await
+            .map_err(ApiError::Params)?;
+
+        Ok(Self(params))
+    }
+}
+
+/// Error of the API, answered with a JSON body. The errors of the server are logged and
+/// their details aren't exposed.
+enum ApiError {
+    /// The search failed, ie. the query is invalid or the indexes can't be read
+    Search(Error),
+    /// The query string is missing parameters or has invalid ones
+    Params(QueryRejection),
+    /// The admin token is missing or wrong
+    Forbidden,
+    /// The indexes couldn't be reloaded, the previous ones are still served
+    Reload(String),
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut position = None;
+
+        let (status, error, message) = match &self {
+            ApiError::Search(err @ Error::QuerySyntax { position: at, .. }) => {
+                position = *at;
+                (StatusCode::BAD_REQUEST, "query_syntax", err.to_string())
+            }
+            ApiError::Search(
+                Error::MissingIndex(_)
+                | Error::CorruptIndex { .. }
+                | Error::SchemaVersion { .. }
+                | Error::SchemaMismatch(_),
+            ) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "index_unavailable",
+                "The indexes are unavailable".to_string(),
+            ),
+            ApiError::Search(
+                Error::MissingStoredField { .. }
+                | Error::ReadOnly(_)
+                | Error::Index(_)
+                | Error::Io(_),
+            ) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal",
+                "Internal error".to_string(),
+            ),
+            ApiError::Params(rejection) => {
+                (rejection.status(), "invalid_params", rejection.body_text())
+            }
+            ApiError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "forbidden",
+                "The admin token is missing or wrong".to_string(),
+            ),
+            ApiError::Reload(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "reload_failed",
+                "The indexes couldn't be reloaded".to_string(),
+            ),
+        };
+
+        match &self {
+            ApiError::Search(err) if status.is_server_error() => {
+                eprintln!("Search failed: {}", err)
+            }
+            ApiError::Reload(err) => eprintln!("Failed to reload the indexes: {}", err),
+            _ => {}
+        }
+
+        let response = ErrorResponse {
+            error,
+            message,
+            position,
+        };
+
+        (status, Json(response)).into_response()
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    /// Kind of the error: `query_syntax`, `invalid_params`, `forbidden`,
+    /// `index_unavailable`, `reload_failed` or `internal`
+    error: &'static str,
+    message: String,
+    /// Byte offset of the error in the query, for `query_syntax` errors when it's known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+}
+
+// the output to our `search` handler
+#[derive(Serialize)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+    /// Number of all matching documents, or chapters with `group=true`
+    total: usize,
+    /// More documents matched than can be paged to, `total` counts the ones which can
+    truncated: bool,
+    page: usize,
+    per_page: usize,
+    duration_milis: u128,
+    /// Rewrites of the query by the synonyms, with `debug=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expansions: Option<Vec<String>>,
+    /// Parts of the query which were ignored
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ignored: Vec<String>,
+    /// Number of matching documents in each book, regardless of `books`
+    books: Vec<BookCount>,
+    /// Query with the misspelled words corrected
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion: Option<String>,
+    /// The results are of the suggestion, with `autocorrect=true` when the query had none
+    autocorrected: bool,
+}
+
+#[derive(Serialize)]
+struct SuggestResponse {
+    /// Completed queries, the most frequent first
+    suggestions: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct ReloadResponse {
+    /// Current generation, none for indexes built before there were generations
+    generation: Option<String>,
+    page_documents: u64,
+    code_documents: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Params {
+    q: String,
+    /// Page number, starting from 1
+    page: Option<u32>,
+    per_page: Option<u32>,
+    /// Source names of the searched books, or of the excluded ones prefixed with `-`,
+    /// separated by commas
+    #[serde(default)]
+    books: String,
+    /// Search the suggested correction of a query without results instead
+    #[serde(default)]
+    autocorrect: bool,
+    /// Return whole bodies of code blocks, not only their snippets
+    #[serde(default)]
+    full_body: bool,
+    /// Group the results by chapters, `page`, `per_page` and `total` count the chapters
+    #[serde(default)]
+    group: bool,
+    #[serde(default)]
+    format: Format,
+    #[serde(default)]
+    debug: bool,
+}
+
+/// Format of the text fields of the results.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// Plain text with the ranges of the highlighted words
+    #[default]
+    Text,
+    /// Escaped HTML with the highlighted words in `<b>` tags
+    Html,
+}
+
+#[derive(Debug, Deserialize)]
+struct SuggestParams {
+    q: String,
+    /// Number of completions
+    limit: Option<u32>,
+}
@@ -0,0 +1,346 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::Deserialize;
+
+use crate::parsers::{
+    parse_html_page, parse_md_sections, parse_summary_chapters, CodeBlock, Section,
+};
+
+/// A source of documents: a book, a directory of pages, etc.
+///
+/// Implement it to index documents the built-in sources don't know about.
+pub trait DocumentSource {
+    /// Title of the source, ie. the book title
+    fn title(&self) -> &str;
+
+    /// Documents of the source. Files which can't be read are yielded as errors, so the
+    /// rest of the source still gets indexed.
+    fn documents(&self) -> Box<dyn Iterator<Item = Result<SourceDocument, SourceError>> + '_>;
+}
+
+/// A document yielded by a source, ie. a section of a chapter.
+#[derive(Debug, Default, Clone)]
+pub struct SourceDocument {
+    /// Url of the document, with an anchor for sections
+    pub url: String,
+    /// Chapter title
+    pub title: String,
+    pub body: String,
+    pub code_blocks: Vec<CodeBlock>,
+    pub metadata: Metadata,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Metadata {
+    /// Section heading, empty for the content preceding the first heading
+    pub heading: String,
+    /// File the document was read from
+    pub path: PathBuf,
+    /// Titles of the chapter and its parents in the summary, from the top level one
+    pub chapter_path: Vec<String>,
+    /// Last modification of the file
+    pub modified: Option<SystemTime>,
+}
+
+/// A file of a source which couldn't be read.
+#[derive(Debug)]
+pub struct SourceError {
+    pub path: PathBuf,
+    /// Url of the chapter of the file, None when the source itself can't be read
+    pub url: Option<String>,
+    pub error: std::io::Error,
+}
+
+impl fmt::Display for SourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Couldn't read {:?}: {}", self.path, self.error)
+    }
+}
+
+impl std::error::Error for SourceError {}
+
+/// A source in `config.toml`:
+///
+/// ```toml
+/// [[sources]]
+/// type = "mdbook"
+/// name = "nomicon"
+/// title = "The Rustonomicon"
+/// base_url = "https://doc.rust-lang.org/nomicon"
+/// directory = "mdbooks/nomicon/src"
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct SourceConfig {
+    /// Short name of the source, ie. `nomicon`
+    pub name: String,
+    pub title: String,
+    pub base_url: String,
+    pub directory: PathBuf,
+    #[serde(flatten)]
+    pub options: SourceOptions,
+}
+
+/// Type of the source and its options.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SourceOptions {
+    /// mdBook sources, chapters are listed in the summary
+    Mdbook {
+        #[serde(default = "default_summary")]
+        summary: String,
+    },
+    /// Directory of markdown files
+    MarkdownDir {
+        #[serde(default = "default_md_extension")]
+        extension: String,
+    },
+    /// Directory of HTML files
+    HtmlDir {
+        #[serde(default = "default_html_extension")]
+        extension: String,
+    },
+}
+
+fn default_summary() -> String {
+    "SUMMARY.md".to_string()
+}
+
+fn default_md_extension() -> String {
+    "md".to_string()
+}
+
+fn default_html_extension() -> String {
+    "html".to_string()
+}
+
+impl SourceConfig {
+    pub fn build(&self) -> Box<dyn DocumentSource> {
+        let title = self.title.clone();
+        let base_url = self.base_url.trim_end_matches('/').to_string();
+        let directory = self.directory.clone();
+
+        match &self.options {
+            SourceOptions::Mdbook { summary } => Box::new(MdbookSource {
+                title,
+                base_url,
+                directory,
+                summary: summary.clone(),
+            }),
+            SourceOptions::MarkdownDir { extension } => Box::new(MarkdownDirSource {
+                title,
+                base_url,
+                directory,
+                extension: extension.clone(),
+            }),
+            SourceOptions::HtmlDir { extension } => Box::new(HtmlDirSource {
+                title,
+                base_url,
+                directory,
+                extension: extension.clone(),
+            }),
+        }
+    }
+}
+
+/// mdBook sources, chapter `{directory}/ch01.md` listed in the summary becomes
+/// `{base_url}/ch01.html`.
+pub struct MdbookSource {
+    pub title: String,
+    pub base_url: String,
+    pub directory: PathBuf,
+    pub summary: String,
+}
+
+impl DocumentSource for MdbookSource {
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn documents(&self) -> Box<dyn Iterator<Item = Result<SourceDocument, SourceError>> + '_> {
+        let path = self.directory.join(&self.summary);
+        println!("Indexing {:?}", path);
+
+        // Parse chapters from SUMMARY.md
+        let chapters = match fs::read_to_string(&path) {
+            Ok(buf) => parse_summary_chapters(&buf),
+            Err(error) => {
+                return Box::new(std::iter::once(Err(SourceError {
+                    path,
+                    url: None,
+                    error,
+                })))
+            }
+        };
+
+        Box::new(chapters.into_iter().flat_map(move |chapter| {
+            let url = format!("{}/{}.html", self.base_url, chapter.rel_url);
+            let path = self.directory.join(format!("{}.md", chapter.rel_url));
+            read_md_chapter(url, chapter.path, path)
+        }))
+    }
+}
+
+/// Directory of markdown files, `{directory}/intro.md` becomes `{base_url}/intro`.
+pub struct MarkdownDirSource {
+    pub title: String,
+    pub base_url: String,
+    pub directory: PathBuf,
+    pub extension: String,
+}
+
+impl DocumentSource for MarkdownDirSource {
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn documents(&self) -> Box<dyn Iterator<Item = Result<SourceDocument, SourceError>> + '_> {
+        println!("Indexing md files from {:?}", self.directory);
+
+        let files = match list_files(&self.directory, &self.extension) {
+            Ok(files) => files,
+            Err(err) => return Box::new(std::iter::once(Err(err))),
+        };
+
+        Box::new(files.into_iter().flat_map(move |path| {
+            let file_stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let url = format!("{}/{}", self.base_url, file_stem);
+            read_md_chapter(url, vec![file_stem.to_string()], path)
+        }))
+    }
+}
+
+/// Directory of HTML files, `{directory}/intro.html` becomes `{base_url}/intro.html`.
+pub struct HtmlDirSource {
+    pub title: String,
+    pub base_url: String,
+    pub directory: PathBuf,
+    pub extension: String,
+}
+
+impl DocumentSource for HtmlDirSource {
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn documents(&self) -> Box<dyn Iterator<Item = Result<SourceDocument, SourceError>> + '_> {
+        println!("Indexing html files from {:?}", self.directory);
+
+        let files = match list_files(&self.directory, &self.extension) {
+            Ok(files) => files,
+            Err(err) => return Box::new(std::iter::once(Err(err))),
+        };
+
+        Box::new(files.into_iter().map(move |path| {
+            println!("Indexing {:?}", &path);
+
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let url = format!("{}/{}", self.base_url, file_name);
+
+            let buf = fs::read_to_string(&path).map_err(|error| SourceError {
+                path: path.clone(),
+                url: Some(url.clone()),
+                error,
+            })?;
+            let (body, code_blocks, chapter_title) = parse_html_page(&buf);
+
+            let title = chapter_title.unwrap_or_else(|| file_name.to_string());
+
+            Ok(SourceDocument {
+                url,
+                title: title.clone(),
+                body,
+                code_blocks,
+                metadata: Metadata {
+                    modified: modified(&path),
+                    path,
+                    chapter_path: vec![title],
+                    ..Default::default()
+                },
+            })
+        }))
+    }
+}
+
+/// Reads a markdown chapter into a document per section. The chapter title is the
+/// last one of its path.
+fn read_md_chapter(
+    url: String,
+    chapter_path: Vec<String>,
+    path: PathBuf,
+) -> Vec<Result<SourceDocument, SourceError>> {
+    println!("Indexing {:?}", &path);
+
+    let buf = match fs::read_to_string(&path) {
+        Ok(buf) => buf,
+        Err(error) => {
+            return vec![Err(SourceError {
+                path,
+                url: Some(url),
+                error,
+            })]
+        }
+    };
+
+    // Includes are relative to the chapter
+    let md_dir = path.parent().unwrap_or(Path::new(""));
+    let sections = parse_md_sections(&buf, &md_dir.to_string_lossy());
+    let metadata = Metadata {
+        modified: modified(&path),
+        path,
+        chapter_path,
+        ..Default::default()
+    };
+
+    sections
+        .into_iter()
+        .map(|section| Ok(section_document(&url, &metadata, section)))
+        .collect()
+}
+
+/// Document of a section, linked with the `url#anchor`.
+fn section_document(url: &str, chapter: &Metadata, section: Section) -> SourceDocument {
+    let url = match section.anchor.is_empty() {
+        true => url.to_string(),
+        false => format!("{}#{}", url, section.anchor),
+    };
+
+    SourceDocument {
+        url,
+        title: chapter.chapter_path.last().cloned().unwrap_or_default(),
+        body: section.body,
+        code_blocks: section.code_blocks,
+        metadata: Metadata {
+            heading: section.heading,
+            ..chapter.clone()
+        },
+    }
+}
+
+/// Last modification of the file, if the filesystem records it.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Files with the extension in the directory, sorted by name.
+fn list_files(directory: &Path, extension: &str) -> Result<Vec<PathBuf>, SourceError> {
+    let entries = fs::read_dir(directory).map_err(|error| SourceError {
+        path: directory.to_path_buf(),
+        url: None,
+        error,
+    })?;
+
+    let mut files: Vec<_> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+
+    files.sort();
+
+    Ok(files)
+}
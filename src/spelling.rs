@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+use crate::index::{fuzzy_distance, SearchIndex};
+use crate::query::UserQuery;
+
+/// Corrections of misspelled words of queries, ie. `lifetimes` for `lifetmies`.
+///
+/// Words are corrected to the words of the indexes within the edit distance of the
+/// fuzzy search, the closest ones first and then the ones in the most documents. The
+/// words are in a BK-tree, so only the ones at a close distance of the nodes on the
+/// way are compared.
+#[derive(Debug, Default, Clone)]
+pub struct Suggester {
+    /// Nodes of the tree by the ordinal of their word, the first one is the root
+    nodes: Vec<Node>,
+    /// Ordinals of the words
+    words: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    word: String,
+    chars: Vec<char>,
+    /// Number of documents with the word
+    doc_freq: u64,
+    /// Subtrees of the words at a distance from this one, by the distance
+    children: Vec<(usize, usize)>,
+}
+
+impl Suggester {
+    /// Collects the words of the indexes.
+    pub fn from_indexes(indexes: &[&SearchIndex]) -> Result<Self, Error> {
+        let mut suggester = Self::default();
+
+        for index in indexes {
+            for (word, doc_freq) in index.word_frequencies()? {
+                suggester.add(&word, doc_freq);
+            }
+        }
+
+        Ok(suggester)
+    }
+
+    /// Adds the word found in `doc_freq` documents, only words of letters are
+    /// suggested.
+    pub fn add(&mut self, word: &str, doc_freq: u64) {
+        if !word.chars().all(char::is_alphabetic) {
+            return;
+        }
+
+        let word = word.to_lowercase();
+        if let Some(&ordinal) = self.words.get(&word) {
+            self.nodes[ordinal].doc_freq += doc_freq;
+            return;
+        }
+
+        let ordinal = self.nodes.len();
+        let chars: Vec<char> = word.chars().collect();
+
+        // Child of the node whose subtree has no word at its distance yet
+        let mut parent = 0;
+        while parent < ordinal {
+            let distance = damerau_distance(&chars, &self.nodes[parent].chars);
+            let node = &mut self.nodes[parent];
+            match node.children.iter().find(|(d, _)| *d == distance) {
+                Some(&(_, child)) => parent = child,
+                None => {
+                    node.children.push((distance, ordinal));
+                    break;
+                }
+            }
+        }
+
+        self.words.insert(word.clone(), ordinal);
+        self.nodes.push(Node {
+            word,
+            chars,
+            doc_freq,
+            children: vec![],
+        });
+    }
+
+    /// The query with its misspelled words corrected, None when there is nothing to
+    /// correct. Only the searched words are corrected, not the phrases and filters.
+    pub fn suggest(&self, q: &str) -> Option<String> {
+        let query = UserQuery::parse(q);
+
+        let corrections: HashMap<&str, String> = query
+            .words
+            .iter()
+            .filter_map(|word| Some((word.as_str(), self.correct(word)?)))
+            .collect();
+
+        if corrections.is_empty() {
+            return None;
+        }
+
+        let suggestion = q
+            .split_whitespace()
+            .map(|part| corrections.get(part).map_or(part, String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        Some(suggestion)
+    }
+
+    /// Correction of the word, None for known words, words with other characters than
+    /// letters and words without a close one.
+    pub fn correct(&self, word: &str) -> Option<String> {
+        let word = word.to_lowercase();
+        if !word.chars().all(char::is_alphabetic) || self.words.contains_key(&word) {
+            return None;
+        }
+
+        let max_distance = usize::from(fuzzy_distance(&word));
+        if max_distance == 0 {
+            return None;
+        }
+
+        let chars: Vec<char> = word.chars().collect();
+
+        self.within(&chars, max_distance)
+            .into_iter()
+            .filter_map(|node| {
+                let distance = edit_distance(&chars, &node.word);
+                (distance <= max_distance).then_some((distance, node.doc_freq, &node.word))
+            })
+            .min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(b.2)))
+            .map(|(_, _, candidate)| candidate.clone())
+    }
+
+    /// Nodes of the words within the Damerau-Levenshtein distance of the word. It's
+    /// never more than the edit distance, so they include the ones within the edit
+    /// distance.
+    fn within(&self, chars: &[char], max_distance: usize) -> Vec<&Node> {
+        let mut found = Vec::new();
+        let mut stack = match self.nodes.is_empty() {
+            true => vec![],
+            false => vec![0],
+        };
+
+        while let Some(ordinal) = stack.pop() {
+            let node = &self.nodes[ordinal];
+            let distance = damerau_distance(chars, &node.chars);
+            if distance <= max_distance {
+                found.push(node);
+            }
+
+            // By the triangle inequality, farther subtrees have no word within the
+            // distance
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| d.abs_diff(distance) <= max_distance)
+                    .map(|&(_, child)| child),
+            );
+        }
+
+        found
+    }
+}
+
+/// Edit distance where swapping two adjacent characters is one edit, like in the
+/// fuzzy search.
+fn edit_distance(a: &[char], b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+
+    // Rows of the distances of the prefixes of `a` to the prefixes of `b`
+    let mut before_previous: Vec<usize> = vec![0; b.len() + 1];
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        let mut current = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            current[j] = (previous[j] + 1)
+                .min(current[j - 1] + 1)
+                .min(previous[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                current[j] = current[j].min(before_previous[j - 2] + 1);
+            }
+        }
+        before_previous = std::mem::replace(&mut previous, current);
+    }
+
+    previous[b.len()]
+}
+
+/// Edit distance where swapping two adjacent characters is one edit, even when they
+/// are edited further. Unlike `edit_distance` it's a metric, as the BK-tree needs.
+fn damerau_distance(a: &[char], b: &[char]) -> usize {
+    let max = a.len() + b.len();
+    let width = b.len() + 2;
+
+    // Distances of the prefixes of `a` to the prefixes of `b`, from the second row and
+    // column, the first ones are the maximum
+    let mut distances = vec![0; (a.len() + 2) * width];
+    distances[0] = max;
+    for i in 0..=a.len() {
+        distances[(i + 1) * width] = max;
+        distances[(i + 1) * width + 1] = i;
+    }
+    for j in 0..=b.len() {
+        distances[j + 1] = max;
+        distances[width + j + 1] = j;
+    }
+
+    // Last row of `a` with each character
+    let mut last_rows = HashMap::<char, usize>::new();
+
+    for i in 1..=a.len() {
+        // Last column of `b` with the character of the row
+        let mut last_column = 0;
+
+        for j in 1..=b.len() {
+            let last_row = last_rows.get(&b[j - 1]).copied().unwrap_or(0);
+            let transposed_column = last_column;
+            let cost = match a[i - 1] == b[j - 1] {
+                true => {
+                    last_column = j;
+                    0
+                }
+                false => 1,
+            };
+
+            distances[(i + 1) * width + j + 1] = (distances[i * width + j] + cost)
+                .min(distances[(i + 1) * width + j] + 1)
+                .min(distances[i * width + j + 1] + 1)
+                .min(
+                    distances[last_row * width + transposed_column]
+                        + (i - last_row - 1)
+                        + 1
+                        + (j - transposed_column - 1),
+                );
+        }
+
+        last_rows.insert(a[i - 1], i);
+    }
+
+    distances[(a.len() + 1) * width + b.len() + 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn suggester() -> Suggester {
+        let mut suggester = Suggester::default();
+        for (word, doc_freq) in [
+            ("lifetimes", 40),
+            ("lifetime", 25),
+            ("borrow", 30),
+            ("barrow", 1),
+            ("trait", 50),
+            ("HashMap", 12),
+            ("u32", 9),
+        ] {
+            suggester.add(word, doc_freq);
+        }
+        suggester
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+
+        assert_eq!(edit_distance(&chars("lifetmies"), "lifetimes"), 1);
+        assert_eq!(edit_distance(&chars("borow"), "borrow"), 1);
+        assert_eq!(edit_distance(&chars("trait"), "trait"), 0);
+        assert_eq!(edit_distance(&chars("kitten"), "sitting"), 3);
+
+        assert_eq!(
+            damerau_distance(&chars("lifetmies"), &chars("lifetimes")),
+            1
+        );
+        assert_eq!(damerau_distance(&chars("kitten"), &chars("sitting")), 3);
+        assert_eq!(damerau_distance(&chars(""), &chars("trait")), 5);
+        // A swap and an insertion between the swapped characters
+        assert_eq!(damerau_distance(&chars("ca"), &chars("abc")), 2);
+        assert_eq!(edit_distance(&chars("ca"), "abc"), 3);
+    }
+
+    #[test]
+    fn test_suggest() {
+        let suggester = suggester();
+
+        assert_eq!(suggester.correct("lifetmies").as_deref(), Some("lifetimes"));
+        // The one in more documents
+        assert_eq!(suggester.correct("borow").as_deref(), Some("borrow"));
+        assert_eq!(suggester.correct("Hashmap"), None);
+        assert_eq!(suggester.correct("trat").as_deref(), Some("trait"));
+        // Short words aren't corrected
+        assert_eq!(suggester.correct("tra"), None);
+        assert_eq!(suggester.correct("u33"), None);
+
+        assert_eq!(
+            suggester
+                .suggest("lifetmies -borow book:nomicon")
+                .as_deref(),
+            Some("lifetimes -borow book:nomicon")
+        );
+        assert_eq!(suggester.suggest("trait \"borow lifetmies\""), None);
+    }
+
+    #[test]
+    fn test_tree_finds_all_close_words() {
+        let words = [
+            "borrow",
+            "borrows",
+            "barrow",
+            "burrow",
+            "arrow",
+            "borrowed",
+            "row",
+            "brow",
+            "trait",
+            "traits",
+            "tract",
+            "strait",
+            "treat",
+            "lifetime",
+            "lifetimes",
+        ];
+        let mut suggester = Suggester::default();
+        for word in words {
+            suggester.add(word, 1);
+        }
+
+        for typo in ["borow", "brrow", "tarit", "trats", "lifetmie", "arrwo"] {
+            let chars: Vec<char> = typo.chars().collect();
+            let mut found: Vec<&str> = suggester
+                .within(&chars, 2)
+                .iter()
+                .map(|node| node.word.as_str())
+                .collect();
+            found.sort();
+
+            let mut expected: Vec<&str> = words
+                .into_iter()
+                .filter(|word| damerau_distance(&chars, &word.chars().collect::<Vec<_>>()) <= 2)
+                .collect();
+            expected.sort();
+
+            assert_eq!(found, expected, "{}", typo);
+        }
+    }
+}
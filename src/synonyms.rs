@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Default file with the synonyms
+pub const SYNONYMS_FILE: &str = "synonyms.toml";
+
+/// Maximal number of rewrites of a query
+const MAX_EXPANSIONS: usize = 8;
+
+/// Rewrites of queries to the terms used by the books, ie. `vector` to `vec`.
+///
+/// Rules are read from a TOML file:
+///
+/// ```toml
+/// # Words with the same meaning, each one expands to the others
+/// synonyms = [["vec", "vector"], ["hashmap", "hash map"]]
+///
+/// # One-way expansions, `rc` expands to `reference counting` but not the other way
+/// [expansions]
+/// rc = ["reference counting"]
+///
+/// # Abbreviations expand to their long form and back
+/// [abbreviations]
+/// ffi = "foreign function interface"
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Synonyms {
+    rules: Vec<Rule>,
+}
+
+/// Words of a query rewritten to each of the alternatives.
+#[derive(Debug, Clone)]
+struct Rule {
+    words: Vec<String>,
+    alternatives: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SynonymsFile {
+    synonyms: Vec<Vec<String>>,
+    expansions: BTreeMap<String, Vec<String>>,
+    abbreviations: BTreeMap<String, String>,
+}
+
+fn words(s: &str) -> Vec<String> {
+    s.split_whitespace()
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+impl Synonyms {
+    /// Loads the rules from the file, a missing file has no rules.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(buf) => Self::parse(&buf),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn parse(s: &str) -> io::Result<Self> {
+        let file: SynonymsFile =
+            toml::from_str(s).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        let mut synonyms = Self::default();
+
+        for group in &file.synonyms {
+            for word in group {
+                let others = group.iter().filter(|other| *other != word).cloned();
+                synonyms.add(word, others.collect());
+            }
+        }
+
+        for (words, alternatives) in file.expansions {
+            synonyms.add(&words, alternatives);
+        }
+
+        for (abbreviation, long_form) in file.abbreviations {
+            synonyms.add(&abbreviation, vec![long_form.clone()]);
+            synonyms.add(&long_form, vec![abbreviation]);
+        }
+
+        Ok(synonyms)
+    }
+
+    fn add(&mut self, from: &str, alternatives: Vec<String>) {
+        let words = words(from);
+        if !words.is_empty() && !alternatives.is_empty() {
+            self.rules.push(Rule {
+                words,
+                alternatives,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rewrites of the query, a rewrite replaces one occurrence of the words of a rule
+    /// with one of its alternatives. Alternatives of several words are phrases.
+    pub fn expand(&self, q: &str) -> Vec<String> {
+        let query = words(q);
+        let mut expansions = Vec::new();
+
+        for rule in &self.rules {
+            let n = rule.words.len();
+
+            for start in 0..(query.len() + 1).saturating_sub(n) {
+                if query[start..start + n] != rule.words[..] {
+                    continue;
+                }
+
+                for alternative in &rule.alternatives {
+                    let alternative = match alternative.contains(char::is_whitespace) {
+                        true => format!("\"{}\"", alternative),
+                        false => alternative.clone(),
+                    };
+
+                    let expansion = query[..start]
+                        .iter()
+                        .chain(std::iter::once(&alternative))
+                        .chain(&query[start + n..])
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(" ");
+
+                    if !expansions.contains(&expansion) && words(&expansion) != query {
+                        expansions.push(expansion);
+                    }
+                }
+            }
+        }
+
+        expansions.truncate(MAX_EXPANSIONS);
+        expansions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        synonyms = [["vec", "vector"], ["hashmap", "hash map"]]
+
+        [expansions]
+        rc = ["Rc", "reference counting"]
+
+        [abbreviations]
+        ffi = "foreign function interface"
+    "#;
+
+    #[test]
+    fn test_synonyms_are_bidirectional() {
+        let synonyms = Synonyms::parse(RULES).unwrap();
+
+        assert_eq!(synonyms.expand("Vector push"), vec!["vec push"]);
+        assert_eq!(synonyms.expand("vec push"), vec!["vector push"]);
+        assert_eq!(
+            synonyms.expand("insert into hash map"),
+            vec!["insert into hashmap"]
+        );
+        assert_eq!(synonyms.expand("hashmap"), vec!["\"hash map\""]);
+    }
+
+    #[test]
+    fn test_expansions_are_one_way() {
+        let synonyms = Synonyms::parse(RULES).unwrap();
+
+        assert_eq!(
+            synonyms.expand("rc cycle"),
+            vec!["\"reference counting\" cycle"]
+        );
+        assert!(synonyms.expand("reference counting").is_empty());
+    }
+
+    #[test]
+    fn test_abbreviations() {
+        let synonyms = Synonyms::parse(RULES).unwrap();
+
+        assert_eq!(
+            synonyms.expand("FFI"),
+            vec!["\"foreign function interface\""]
+        );
+        assert_eq!(synonyms.expand("foreign function interface"), vec!["ffi"]);
+    }
+
+    #[test]
+    fn test_default_synonyms_file() {
+        let synonyms = Synonyms::load(SYNONYMS_FILE).unwrap();
+
+        assert!(!synonyms.is_empty());
+        assert!(synonyms.expand("vector").contains(&"vec".to_string()));
+    }
+}
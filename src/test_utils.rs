@@ -0,0 +1,92 @@
+//! Sources and indexes of the tests, built in temporary directories which are removed
+//! when the tests end, whether they pass or not.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::index::{IndexDocument, IndexKind, SearchIndex};
+use crate::indexer::Indexer;
+use crate::parsers::CodeBlock;
+use crate::sources::{DocumentSource, Metadata, SourceDocument, SourceError};
+use crate::tokenizer::AnalyzerConfig;
+
+/// Source of documents kept in memory.
+#[derive(Default)]
+pub struct TestSource {
+    pub title: &'static str,
+    pub documents: Vec<SourceDocument>,
+    /// Urls of the chapters whose files can't be read, None when the source itself
+    /// can't be read
+    pub unreadable: Vec<Option<&'static str>>,
+}
+
+impl DocumentSource for TestSource {
+    fn title(&self) -> &str {
+        self.title
+    }
+
+    fn documents(&self) -> Box<dyn Iterator<Item = Result<SourceDocument, SourceError>> + '_> {
+        let errors = self.unreadable.iter().map(|url| {
+            Err(SourceError {
+                path: PathBuf::from(url.unwrap_or(self.title)),
+                url: url.map(str::to_string),
+                error: io::Error::from(io::ErrorKind::PermissionDenied),
+            })
+        });
+
+        Box::new(self.documents.iter().cloned().map(Ok).chain(errors))
+    }
+}
+
+/// Document of a chapter, the title is its whole chapter path.
+pub fn document(url: &str, title: &str, body: &str, code_blocks: Vec<CodeBlock>) -> SourceDocument {
+    SourceDocument {
+        url: url.to_string(),
+        title: title.to_string(),
+        body: body.to_string(),
+        code_blocks,
+        metadata: Metadata {
+            chapter_path: vec![title.to_string()],
+            ..Default::default()
+        },
+    }
+}
+
+/// Rust code blocks of the code.
+pub fn rust(code: &[&str]) -> Vec<CodeBlock> {
+    code.iter().map(|code| CodeBlock::rust(code)).collect()
+}
+
+/// Indexes the sources by their names into the directory and commits them.
+pub fn index_sources(dir: &Path, sources: &[(&str, &TestSource)]) {
+    let mut indexer = Indexer::open(dir, &AnalyzerConfig::default()).unwrap();
+    for (name, source) in sources {
+        indexer.index(name, *source).unwrap();
+    }
+    indexer.commit().unwrap();
+}
+
+/// Document of a page with the id as its url.
+pub fn page(id: &str, body: &str) -> IndexDocument {
+    IndexDocument {
+        id: id.to_string(),
+        url: id.to_string(),
+        body: body.to_string(),
+        ..Default::default()
+    }
+}
+
+/// Creates an index of the kind with the documents in the directory, and opens it for
+/// searching.
+pub fn create_index(dir: &Path, kind: IndexKind, documents: Vec<IndexDocument>) -> SearchIndex {
+    let dir = dir.to_string_lossy();
+
+    let mut index = SearchIndex::create(&dir, kind, &AnalyzerConfig::default()).unwrap();
+    for document in documents {
+        index.add_document(document).unwrap();
+    }
+    index.commit().unwrap();
+    drop(index);
+
+    SearchIndex::open(&dir).unwrap()
+}
@@ -0,0 +1,530 @@
+use serde::{Deserialize, Serialize};
+use tantivy::tokenizer::{
+    AsciiFoldingFilter, Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer,
+    StopWordFilter, TextAnalyzer, Token, TokenStream, Tokenizer,
+};
+use tantivy::Index;
+
+/// Name of the Rust code analyzer in the tantivy tokenizer manager
+pub const RUST_CODE_TOKENIZER: &str = "rust_code";
+
+/// Name of the prose analyzer, stemmed and without stopwords
+pub const PROSE_TOKENIZER: &str = "prose";
+
+/// Name of the analyzer of the unstemmed shadow fields
+pub const EXACT_TOKENIZER: &str = "prose_exact";
+
+/// Analysis of the prose in the page index, `[analyzer]` in `config.toml`:
+///
+/// ```toml
+/// [analyzer]
+/// language = "English"
+/// stemming = true
+/// stopwords = true
+/// ascii_folding = true
+/// exact_fields = true
+/// ```
+///
+/// It's stored with the index, so the index is searched the way it was built.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnalyzerConfig {
+    pub language: Language,
+    /// Reduce words to their stem, "borrowing" matches "borrowed"
+    pub stemming: bool,
+    /// Drop the stopwords of the language
+    pub stopwords: bool,
+    /// Fold accented letters, "naïve" matches "naive"
+    pub ascii_folding: bool,
+    /// Keep unstemmed copies of the title and body, exact forms of words rank higher
+    pub exact_fields: bool,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            language: Language::English,
+            stemming: true,
+            stopwords: true,
+            ascii_folding: true,
+            exact_fields: true,
+        }
+    }
+}
+
+/// Operators kept as tokens, longer ones first so they win over their prefixes.
+const OPERATORS: [&str; 19] = [
+    "..=", "::", "=>", "->", "==", "!=", "<=", ">=", "&&", "||", "..", "?", "&", "*", "<", ">",
+    "=", "!", "|",
+];
+
+/// Tokenizer of Rust code.
+///
+/// Compound tokens are kept whole and split into their parts, the whole token shares
+/// the position of its first part, so phrase queries match either form:
+///
+/// - paths: `std::sync::Arc` is `std::sync::Arc`, `std`, `sync`, `Arc`
+/// - identifiers: `HashMap` is `HashMap`, `Hash`, `Map` and `hash_map` is `hash_map`,
+///   `hash`, `map`
+/// - macros: `println!` is `println!`, `println`
+/// - attributes: `#[derive(Debug)]` is `#[derive]`, `derive`, `Debug`
+/// - lifetimes: `'static` is `'static`, `static`
+/// - `&mut` is `&mut`, `mut`
+///
+/// Operators like `?`, `=>` and `->` are tokens, other punctuation is dropped.
+#[derive(Clone, Default)]
+pub struct RustTokenizer;
+
+/// Analyzer of code fields: the Rust tokenizer, lowercased.
+pub fn rust_code_analyzer() -> TextAnalyzer {
+    TextAnalyzer::builder(RustTokenizer)
+        .filter(RemoveLongFilter::limit(100))
+        .filter(LowerCaser)
+        .build()
+}
+
+/// Analyzer of prose fields.
+pub fn prose_analyzer(config: &AnalyzerConfig) -> TextAnalyzer {
+    let mut builder = exact_analyzer_builder(config);
+
+    if config.stopwords {
+        if let Some(filter) = StopWordFilter::new(config.language) {
+            builder = builder.filter_dynamic(filter);
+        }
+    }
+    if config.stemming {
+        builder = builder.filter_dynamic(Stemmer::new(config.language));
+    }
+
+    builder.build()
+}
+
+/// Analyzer of the unstemmed shadows of prose fields.
+pub fn exact_analyzer(config: &AnalyzerConfig) -> TextAnalyzer {
+    exact_analyzer_builder(config).build()
+}
+
+fn exact_analyzer_builder(config: &AnalyzerConfig) -> tantivy::tokenizer::TextAnalyzerBuilder {
+    // The same as tantivy's default analyzer
+    let mut builder = TextAnalyzer::builder(SimpleTokenizer::default())
+        .filter(RemoveLongFilter::limit(40))
+        .filter(LowerCaser)
+        .dynamic();
+
+    if config.ascii_folding {
+        builder = builder.filter_dynamic(AsciiFoldingFilter);
+    }
+
+    builder
+}
+
+/// Registers the analyzers of the indexes in the index.
+pub fn register_tokenizers(index: &Index, config: &AnalyzerConfig) {
+    let tokenizers = index.tokenizers();
+
+    tokenizers.register(RUST_CODE_TOKENIZER, rust_code_analyzer());
+    tokenizers.register(PROSE_TOKENIZER, prose_analyzer(config));
+    tokenizers.register(EXACT_TOKENIZER, exact_analyzer(config));
+}
+
+pub struct RustTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Tokenizer for RustTokenizer {
+    type TokenStream<'a> = RustTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> RustTokenStream {
+        RustTokenStream {
+            tokens: Scanner::new(text).run(),
+            index: 0,
+        }
+    }
+}
+
+impl TokenStream for RustTokenStream {
+    fn advance(&mut self) -> bool {
+        self.index += 1;
+        self.index <= self.tokens.len()
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+struct Scanner<'a> {
+    text: &'a str,
+    chars: Vec<(usize, char)>,
+    /// Index of the current char
+    i: usize,
+    /// Position of the next token
+    position: usize,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text,
+            chars: text.char_indices().collect(),
+            i: 0,
+            position: 0,
+            tokens: vec![],
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.chars.get(self.i + n).map(|(_, c)| *c)
+    }
+
+    /// Byte offset of the char at the index
+    fn offset(&self, i: usize) -> usize {
+        self.chars
+            .get(i)
+            .map_or(self.text.len(), |(offset, _)| *offset)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.text[self.offset(self.i)..].starts_with(s)
+    }
+
+    /// Index of the char after the identifier starting at `i`
+    fn ident_end(&self, mut i: usize) -> usize {
+        while self
+            .chars
+            .get(i)
+            .is_some_and(|(_, c)| is_ident_continue(*c))
+        {
+            i += 1;
+        }
+        i
+    }
+
+    fn push(&mut self, text: String, from: usize, to: usize, position: usize) {
+        self.tokens.push(Token {
+            offset_from: from,
+            offset_to: to,
+            position,
+            text,
+            position_length: 1,
+        });
+    }
+
+    fn run(mut self) -> Vec<Token> {
+        while let Some(c) = self.peek(0) {
+            if is_ident_start(c) {
+                self.path("");
+            } else if c.is_ascii_digit() {
+                let end = self.ident_end(self.i);
+                let (from, to) = (self.offset(self.i), self.offset(end));
+                self.push(self.text[from..to].to_string(), from, to, self.position);
+                self.position += 1;
+                self.i = end;
+            } else if c == '\'' && self.peek(1).is_some_and(is_ident_start) {
+                self.lifetime();
+            } else if self.starts_with("#[") || self.starts_with("#![") {
+                let prefix = if self.starts_with("#[") { "#[" } else { "#![" };
+                self.i += prefix.len();
+                if self.peek(0).is_some_and(is_ident_start) {
+                    self.path(prefix);
+                }
+            } else if self.starts_with("&mut") && !self.peek(4).is_some_and(is_ident_continue) {
+                // `&mut` shares the position with `mut`, which follows
+                let from = self.offset(self.i);
+                self.push("&mut".to_string(), from, from + 4, self.position);
+                self.i += 1;
+            } else if let Some(operator) = OPERATORS.iter().find(|op| self.starts_with(op)) {
+                let from = self.offset(self.i);
+                self.push(
+                    operator.to_string(),
+                    from,
+                    from + operator.len(),
+                    self.position,
+                );
+                self.position += 1;
+                self.i += operator.len();
+            } else {
+                self.i += 1;
+            }
+        }
+
+        self.tokens
+    }
+
+    /// Lifetime `'a`, or a char literal `'a'` which is tokenized as an identifier.
+    fn lifetime(&mut self) {
+        let end = self.ident_end(self.i + 1);
+
+        if self.chars.get(end).is_some_and(|(_, c)| *c == '\'') {
+            self.i += 1;
+            return;
+        }
+
+        let (from, to) = (self.offset(self.i), self.offset(end));
+        self.push(self.text[from..to].to_string(), from, to, self.position);
+        self.i += 1;
+    }
+
+    /// Path of identifiers separated by `::`, possibly a macro call or an attribute
+    /// when `prefix` is `#[` or `#![`.
+    fn path(&mut self, prefix: &str) {
+        let mut segments = vec![(self.i, self.ident_end(self.i))];
+
+        while let Some((_, end)) = segments.last().copied() {
+            let next = end + 2;
+            let separated = self.text[self.offset(end)..].starts_with("::");
+            if !separated
+                || !self
+                    .chars
+                    .get(next)
+                    .is_some_and(|(_, c)| is_ident_start(*c))
+            {
+                break;
+            }
+            segments.push((next, self.ident_end(next)));
+        }
+
+        let (start, _) = segments[0];
+        let (_, end) = segments[segments.len() - 1];
+        let (from, to) = (self.offset(start), self.offset(end));
+        let path = &self.text[from..to];
+
+        if !prefix.is_empty() {
+            let from = from - prefix.len();
+            self.push(format!("{}{}]", prefix, path), from, to, self.position);
+        } else if segments.len() > 1 {
+            self.push(path.to_string(), from, to, self.position);
+        }
+
+        let is_macro = self.peek_at(end) == Some('!') && self.peek_at(end + 1) != Some('=');
+
+        for (no, (start, end)) in segments.iter().copied().enumerate() {
+            if is_macro && no == segments.len() - 1 {
+                let (from, to) = (self.offset(start), self.offset(end) + 1);
+                self.push(self.text[from..to].to_string(), from, to, self.position);
+            }
+            self.ident(start, end);
+        }
+
+        self.i = if is_macro { end + 1 } else { end };
+    }
+
+    fn peek_at(&self, i: usize) -> Option<char> {
+        self.chars.get(i).map(|(_, c)| *c)
+    }
+
+    /// Identifier, split into its snake_case and CamelCase parts.
+    fn ident(&mut self, start: usize, end: usize) {
+        let mut parts = vec![];
+        let mut part_start = None;
+
+        for i in start..end {
+            let c = self.chars[i].1;
+            let prev = (i > start).then(|| self.chars[i - 1].1);
+            let next = self.peek_at(i + 1).filter(|_| i + 1 < end);
+
+            if c == '_' {
+                if let Some(part_start) = part_start.take() {
+                    parts.push((part_start, i));
+                }
+                continue;
+            }
+
+            // Boundaries of `HashMap`, `HTTPServer` and `u32Value`
+            let boundary = c.is_uppercase()
+                && prev.is_some_and(|prev| {
+                    prev.is_lowercase()
+                        || prev.is_ascii_digit()
+                        || (prev.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+                });
+
+            match part_start {
+                None => part_start = Some(i),
+                Some(from) if boundary => {
+                    parts.push((from, i));
+                    part_start = Some(i);
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(part_start) = part_start {
+            parts.push((part_start, end));
+        }
+
+        let (from, to) = (self.offset(start), self.offset(end));
+
+        if parts.len() != 1 || parts[0] != (start, end) {
+            self.push(self.text[from..to].to_string(), from, to, self.position);
+        }
+
+        for (start, end) in parts {
+            let (from, to) = (self.offset(start), self.offset(end));
+            self.push(self.text[from..to].to_string(), from, to, self.position);
+            self.position += 1;
+        }
+
+        // An identifier made of underscores only
+        if self
+            .tokens
+            .last()
+            .is_some_and(|token| token.position == self.position)
+        {
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(text: &str) -> Vec<(String, usize)> {
+        let mut analyzer = rust_code_analyzer();
+        let mut stream = analyzer.token_stream(text);
+        let mut tokens = vec![];
+
+        while let Some(token) = stream.next() {
+            // Attributes don't cover the closing bracket
+            if !token.text.starts_with('#') {
+                assert_eq!(
+                    text[token.offset_from..token.offset_to].to_lowercase(),
+                    token.text
+                );
+            }
+            tokens.push((token.text.clone(), token.position));
+        }
+
+        tokens
+    }
+
+    fn texts(text: &str) -> Vec<String> {
+        tokens(text).into_iter().map(|(text, _)| text).collect()
+    }
+
+    fn analyze(mut analyzer: TextAnalyzer, text: &str) -> Vec<String> {
+        let mut stream = analyzer.token_stream(text);
+        let mut tokens = vec![];
+
+        while let Some(token) = stream.next() {
+            tokens.push(token.text.clone());
+        }
+
+        tokens
+    }
+
+    #[test]
+    fn test_prose_analyzer() {
+        let config = AnalyzerConfig::default();
+        let text = "The naïve Borrowing of lifetimes";
+
+        assert_eq!(
+            analyze(prose_analyzer(&config), text),
+            ["naiv", "borrow", "lifetim"]
+        );
+        assert_eq!(
+            analyze(exact_analyzer(&config), text),
+            ["the", "naive", "borrowing", "of", "lifetimes"]
+        );
+
+        let config = AnalyzerConfig {
+            stemming: false,
+            stopwords: false,
+            ascii_folding: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            analyze(prose_analyzer(&config), text),
+            ["the", "naïve", "borrowing", "of", "lifetimes"]
+        );
+    }
+
+    #[test]
+    fn test_paths_and_identifiers() {
+        assert_eq!(
+            tokens("std::sync::Arc"),
+            vec![
+                ("std::sync::arc".to_string(), 0),
+                ("std".to_string(), 0),
+                ("sync".to_string(), 1),
+                ("arc".to_string(), 2),
+            ]
+        );
+        assert_eq!(
+            tokens("HashMap::new()"),
+            vec![
+                ("hashmap::new".to_string(), 0),
+                ("hashmap".to_string(), 0),
+                ("hash".to_string(), 0),
+                ("map".to_string(), 1),
+                ("new".to_string(), 2),
+            ]
+        );
+        assert_eq!(
+            texts("read_to_string"),
+            ["read_to_string", "read", "to", "string"]
+        );
+        assert_eq!(
+            texts("HTTPServer u32"),
+            ["httpserver", "http", "server", "u32"]
+        );
+    }
+
+    #[test]
+    fn test_macros_attributes_and_lifetimes() {
+        assert_eq!(
+            texts("println!(\"{}\", x != y);"),
+            ["println!", "println", "x", "!=", "y"]
+        );
+        assert_eq!(texts("#[derive(Debug)]"), ["#[derive]", "derive", "debug"]);
+        assert_eq!(texts("#![allow(dead_code)]")[0], "#![allow]");
+        assert_eq!(
+            tokens("&'static str"),
+            vec![
+                ("&".to_string(), 0),
+                ("'static".to_string(), 1),
+                ("static".to_string(), 1),
+                ("str".to_string(), 2),
+            ]
+        );
+        assert_eq!(texts("let c = 'a';"), ["let", "c", "=", "a"]);
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(
+            tokens("fn f(&mut self) -> Result<()> { g()? }"),
+            vec![
+                ("fn".to_string(), 0),
+                ("f".to_string(), 1),
+                ("&mut".to_string(), 2),
+                ("mut".to_string(), 2),
+                ("self".to_string(), 3),
+                ("->".to_string(), 4),
+                ("result".to_string(), 5),
+                ("<".to_string(), 6),
+                (">".to_string(), 7),
+                ("g".to_string(), 8),
+                ("?".to_string(), 9),
+            ]
+        );
+        assert_eq!(
+            texts("impl<T: Clone> Some(x) => x"),
+            ["impl", "<", "t", "clone", ">", "some", "x", "=>", "x"]
+        );
+    }
+}